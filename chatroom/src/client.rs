@@ -4,16 +4,24 @@
 extern crate tokio;
 
 use std::io::{BufReader, BufRead};
+use std::sync::Arc;
 
 use structopt::StructOpt;
 use tokio::prelude::*;
 
+use paxos::transport::Transport;
+
 enum Command {
     /// Connect to the given replica
     Connect {
         port: usize,
     },
 
+    /// Connect to the given replica over a Unix domain socket
+    Attach {
+        path: std::path::PathBuf,
+    },
+
     /// Disconnect from the current replica
     Disconnect,
 
@@ -30,10 +38,11 @@ enum Command {
 
 fn usage() {
     println!(
-        "{}{}{}{}{}{}{}",
+        "{}{}{}{}{}{}{}{}",
         "--------------------------------------------------------------\n",
         "Possible commands:\n",
         "connect <PORT> | c <PORT>  -- Connect to server at <PORT>\n",
+        "attach <PATH>  | a <PATH>  -- Connect to server at socket <PATH>\n",
         "disconnect     | d         -- Disconnect from current server\n",
         "get            | g         -- Get chat log from current server\n",
         "put <MSG>      | p <MSG>   -- Write <MSG> to current server\n",
@@ -55,6 +64,11 @@ impl std::str::FromStr for Command {
                 .and_then(|port| port.parse().map_err(|_| ()))
                 .map(|port| Command::Connect { port })
         }
+        | Some("attach") | Some("a") => {
+            iter.next()
+                .ok_or(())
+                .map(|path| Command::Attach { path: path.into() })
+        }
         | Some("put") | Some("p") => {
             iter.next()
                 .ok_or(())
@@ -65,7 +79,24 @@ impl std::str::FromStr for Command {
     }
 }
 
-async fn run(id: usize) {
+/// Negotiate protocol over `stream` and log in if the server requires authentication.
+async fn negotiate(
+    stream: Box<dyn paxos::external::Io>,
+    cluster: paxos::Cluster,
+    codec: paxos::Codec,
+    token: Option<String>,
+) -> Option<(paxos::external::Rx<chatroom::Response>, paxos::external::Tx<chatroom::Command>)> {
+    let credential = token.unwrap_or_default().into_bytes();
+    match await!(paxos::handshake::connect::<chatroom::State, _>(stream, cluster, codec, credential)) {
+    | Ok(stream) => Some(paxos::external::with_codec(stream, codec)),
+    | Err(error) => {
+        println!("[ERROR]: failed to connect to server: {}", error);
+        None
+    }
+    }
+}
+
+async fn run(id: usize, cluster: paxos::Cluster, codec: paxos::Codec, transport: Arc<dyn Transport>, token: Option<String>) {
     let stdin = std::io::stdin();
     let mut stdout = std::io::stdout();
     let mut reader: Option<paxos::external::Rx<chatroom::Response>> = None;
@@ -91,32 +122,40 @@ async fn run(id: usize) {
 
         match command {
         | Command::Connect { port } => {
-            let addr = format!("127.0.0.1:{}", port);
-
-            // Attempt to parse port
-            let addr = match addr.parse::<std::net::SocketAddr>() {
-            | Ok(addr) => addr,
-            | _ => {
-                println!("[ERROR]: invalid port {}", port);
+            // Attempt to connect to server
+            let stream = match await!(transport.connect(port)) {
+            | Ok(stream) => {
+                println!("[RESPONSE]: connected to server at port {}", port);
+                stream
+            }
+            | Err(error) => {
+                println!("[ERROR]: failed to connect to server at port {}: {}", port, error);
                 continue
             }
             };
 
+            if let Some((rx, tx)) = await!(negotiate(stream, cluster, codec, token.clone())) {
+                reader = Some(rx);
+                writer = Some(tx);
+            }
+        }
+        | Command::Attach { path } => {
             // Attempt to connect to server
-            let stream = match await!(tokio::net::TcpStream::connect(&addr)) {
+            let stream = match await!(tokio::net::UnixStream::connect(&path)) {
             | Ok(stream) => {
-                println!("[RESPONSE]: connected to server at port {}", port);
+                println!("[RESPONSE]: connected to server at {}", path.display());
                 stream
             }
             |_ => {
-                println!("[ERROR]: failed to connect to server at port {}", port);
+                println!("[ERROR]: failed to connect to server at {}", path.display());
                 continue
             }
             };
 
-            let (rx, tx) = paxos::external::new(stream);
-            reader = Some(rx);
-            writer = Some(tx);
+            if let Some((rx, tx)) = await!(negotiate(Box::new(stream), cluster, codec, token.clone())) {
+                reader = Some(rx);
+                writer = Some(tx);
+            }
         }
         | Command::Disconnect => {
             reader = None;
@@ -178,10 +217,48 @@ async fn run(id: usize) {
 struct Opt {
     /// Unique client ID
     #[structopt(short = "i", long = "id")]
-    id: usize
+    id: usize,
+
+    /// Connect to servers over Unix domain sockets in this directory instead of TCP
+    #[structopt(long = "unix", parse(from_os_str))]
+    unix: Option<std::path::PathBuf>,
+
+    /// PEM-encoded CA certificates for connecting to servers over TLS
+    #[structopt(long = "ca", parse(from_os_str))]
+    ca: Option<std::path::PathBuf>,
+
+    /// Domain name that server certificates are issued for
+    #[structopt(long = "domain", default_value = "localhost")]
+    domain: String,
+
+    /// UUID of the cluster to connect to
+    #[structopt(long = "cluster")]
+    cluster: paxos::Cluster,
+
+    /// Message encoding, negotiated with the server (bincode, json, or msgpack)
+    #[structopt(long = "codec", default_value = "bincode")]
+    codec: paxos::Codec,
+
+    /// Token for logging in to servers that require authentication
+    #[structopt(long = "token")]
+    token: Option<String>,
 }
 
 fn main() {
     let opt = Opt::from_args();
-    tokio::run_async(run(opt.id))
+    let transport: Arc<dyn Transport> = match opt.unix {
+    | Some(dir) => Arc::new(paxos::transport::Unix::new(dir)),
+    | None => Arc::new(paxos::transport::Tcp),
+    };
+    let transport: Arc<dyn Transport> = match opt.ca {
+    | None => transport,
+    | Some(ca) => match paxos::tls::Tls::client(ca, &opt.domain) {
+        | Ok(tls) => Arc::new(paxos::transport::Tls::new(transport, tls)),
+        | Err(error) => {
+            eprintln!("[ERROR]: {}", error);
+            std::process::exit(1);
+        }
+        },
+    };
+    tokio::run_async(run(opt.id, opt.cluster, opt.codec, transport, opt.token))
 }
@@ -0,0 +1,51 @@
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "chatroom-inspect")]
+struct Opt {
+    /// Compare the files slot by slot instead of printing them
+    #[structopt(short = "d", long = "diff")]
+    diff: bool,
+
+    /// Storage files to decode, such as `replica-00.paxos`
+    #[structopt(parse(from_os_str), required = true)]
+    files: Vec<std::path::PathBuf>,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    let summaries = opt.files.iter()
+        .map(|file| match paxos::inspect::load::<chatroom::State, _>(file) {
+        | Ok(summary) => summary,
+        | Err(error) => {
+            println!("[ERROR]: {}: {}", file.display(), error);
+            std::process::exit(1);
+        }
+        })
+        .collect::<Vec<_>>();
+
+    if !opt.diff {
+        for (file, summary) in opt.files.iter().zip(&summaries) {
+            println!("{}:\n{:#?}", file.display(), summary);
+        }
+        return
+    }
+
+    let diff = paxos::inspect::diff(&summaries);
+    if diff.is_empty() {
+        println!("No differences across {} files", summaries.len());
+        return
+    }
+
+    for (slot, entries) in diff {
+        println!("Slot {}:", slot);
+        for (file, entry) in opt.files.iter().zip(entries) {
+            match entry {
+            | Some(entry) => println!("  {}: {}", file.display(), entry),
+            | None => println!("  {}: <missing>", file.display()),
+            }
+        }
+    }
+    std::process::exit(1);
+}
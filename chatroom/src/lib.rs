@@ -1,4 +1,7 @@
+use std::collections::{HashMap, HashSet};
+
 use serde_derive::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 use paxos;
 
 #[derive(Serialize, Deserialize)]
@@ -53,4 +56,57 @@ impl paxos::State for State {
         },
         }
     }
+    fn digest(&self) -> Option<u64> {
+        // Replicas may run different builds, so hash with a fixed algorithm and encoding
+        let encoded = bincode::serialize(&self.messages).ok()?;
+        let hash = Sha256::digest(&encoded);
+        let mut digest = [0; 8];
+        digest.copy_from_slice(&hash[..8]);
+        Some(u64::from_be_bytes(digest))
+    }
+    fn tag() -> &'static str {
+        "chatroom"
+    }
+}
+
+/// Authenticates clients by token. Each line of a token file has the form
+/// `<ID> <TOKEN>`, optionally followed by `readonly` to forbid `Put` commands.
+#[derive(Clone, Debug, Default)]
+pub struct Tokens {
+    clients: HashMap<Vec<u8>, usize>,
+    readonly: HashSet<usize>,
+}
+
+impl Tokens {
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let file = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let mut tokens = Tokens::default();
+        for line in file.lines().filter(|line| !line.trim().is_empty()) {
+            let mut words = line.split_whitespace();
+            let id = words.next()
+                .and_then(|id| id.parse::<usize>().ok())
+                .ok_or_else(|| format!("invalid client ID in line: {}", line))?;
+            let token = words.next()
+                .ok_or_else(|| format!("missing token in line: {}", line))?;
+            match words.next() {
+            | None => (),
+            | Some("readonly") => { tokens.readonly.insert(id); }
+            | Some(_) => return Err(format!("invalid permission in line: {}", line)),
+            }
+            tokens.clients.insert(token.as_bytes().to_vec(), id);
+        }
+        Ok(tokens)
+    }
+}
+
+impl paxos::Auth<Command> for Tokens {
+    fn authenticate(&self, credential: &[u8]) -> Option<usize> {
+        self.clients.get(credential).cloned()
+    }
+    fn authorize(&self, client_id: &usize, command: &Command) -> bool {
+        match command.mode {
+        | Mode::Get => true,
+        | Mode::Put(_) => !self.readonly.contains(client_id),
+        }
+    }
 }
@@ -0,0 +1,63 @@
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "chatroom-replay")]
+enum Opt {
+    /// Export the executed decisions in a replica storage file
+    #[structopt(name = "export")]
+    Export {
+        /// Replica storage file, such as `replica-00.paxos`
+        #[structopt(parse(from_os_str))]
+        replica: std::path::PathBuf,
+
+        /// Destination for the exported decision log
+        #[structopt(parse(from_os_str))]
+        output: std::path::PathBuf,
+    },
+
+    /// Replay exported decision logs, and check that they produce the same state
+    #[structopt(name = "replay")]
+    Replay {
+        /// Exported decision logs
+        #[structopt(parse(from_os_str), required = true)]
+        logs: Vec<std::path::PathBuf>,
+    },
+}
+
+fn main() {
+    match Opt::from_args() {
+    | Opt::Export { replica, output } => {
+        let log = paxos::replay::export::<chatroom::State, _>(&replica)
+            .and_then(|log| log.write(&output).map(|_| log));
+        match log {
+        | Ok(log) => println!("Exported {} decision(s) to {}", log.commands.len(), output.display()),
+        | Err(error) => {
+            println!("[ERROR]: {}: {}", replica.display(), error);
+            std::process::exit(1);
+        }
+        }
+    }
+    | Opt::Replay { logs: paths } => {
+        let mut logs = Vec::new();
+        for path in &paths {
+            match paxos::replay::Log::read(path) {
+            | Ok(log) => logs.push(log),
+            | Err(error) => {
+                println!("[ERROR]: {}: {}", path.display(), error);
+                std::process::exit(1);
+            }
+            }
+        }
+
+        for (path, log) in paths.iter().zip(&logs) {
+            let state = paxos::replay::replay::<chatroom::State>(log);
+            println!("{} ({} decisions): {:?}", path.display(), log.commands.len(), state.messages);
+        }
+
+        if let Some(index) = paxos::replay::verify::<chatroom::State>(&logs) {
+            println!("[ERROR]: {} diverges from {}", paths[index].display(), paths[0].display());
+            std::process::exit(1);
+        }
+    }
+    }
+}
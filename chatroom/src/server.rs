@@ -21,6 +21,78 @@ struct Opt {
     #[structopt(short = "t", long = "timeout", default_value = "1000")]
     timeout: u64,
 
+    /// Connect servers and clients over Unix domain sockets in this directory instead of TCP
+    #[structopt(long = "unix", parse(from_os_str))]
+    unix: Option<std::path::PathBuf>,
+
+    /// Unix domain socket to also listen on for client requests
+    #[structopt(long = "socket", parse(from_os_str))]
+    socket: Option<std::path::PathBuf>,
+
+    /// Port to listen on for fault injection (for testing only)
+    #[structopt(short = "f", long = "faults")]
+    faults: Option<usize>,
+
+    /// Port to serve Prometheus metrics on
+    #[structopt(short = "m", long = "metrics")]
+    metrics: Option<usize>,
+
+    /// Port to serve JSON status on
+    #[structopt(short = "a", long = "admin")]
+    admin: Option<usize>,
+
+    /// Directory for stable storage files (defaults to the working directory)
+    #[structopt(long = "storage", parse(from_os_str))]
+    storage: Option<std::path::PathBuf>,
+
+    /// Write structured protocol events to trace-<ID>.jsonl in the storage directory
+    #[structopt(long = "trace")]
+    trace: bool,
+
+    /// PEM-encoded certificate chain for TLS (requires --key and --ca)
+    #[structopt(long = "cert", parse(from_os_str))]
+    cert: Option<std::path::PathBuf>,
+
+    /// PEM-encoded private key for TLS
+    #[structopt(long = "key", parse(from_os_str))]
+    key: Option<std::path::PathBuf>,
+
+    /// PEM-encoded CA certificates for verifying other servers
+    #[structopt(long = "ca", parse(from_os_str))]
+    ca: Option<std::path::PathBuf>,
+
+    /// Domain name that server certificates are issued for
+    #[structopt(long = "domain", default_value = "localhost")]
+    domain: String,
+
+    /// UUID of the cluster this server belongs to (only required on first launch)
+    #[structopt(long = "cluster")]
+    cluster: Option<paxos::Cluster>,
+
+    /// Maximum size of a single frame from peers and clients (in bytes)
+    #[structopt(long = "max-frame")]
+    max_frame: Option<usize>,
+
+    /// Number of malformed frames tolerated per connection
+    #[structopt(long = "max-malformed")]
+    max_malformed: Option<usize>,
+
+    /// Compress large frames between servers (lz4 or zstd)
+    #[structopt(long = "compression")]
+    compression: Option<paxos::Compression>,
+
+    /// Size from which frames between servers are compressed (in bytes)
+    #[structopt(long = "compression-threshold", default_value = "4096")]
+    compression_threshold: usize,
+
+    /// File containing the shared secret that authenticates servers to each other
+    #[structopt(short = "s", long = "secret", parse(from_os_str))]
+    secret: Option<std::path::PathBuf>,
+
+    /// File of client tokens, one `<ID> <TOKEN> [readonly]` per line
+    #[structopt(long = "tokens", parse(from_os_str))]
+    tokens: Option<std::path::PathBuf>,
+
     /// Logging level
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbosity: u8,
@@ -55,7 +127,7 @@ fn main() {
         .apply()
         .unwrap();
 
-    let config = paxos::Config::<chatroom::State>::new(
+    let mut config = paxos::Config::<chatroom::State>::new(
             opt.id,
             opt.port,
             opt.count
@@ -63,5 +135,86 @@ fn main() {
             std::time::Duration::from_millis(opt.timeout)
         );
 
+    if let Some(dir) = opt.unix {
+        config = config.with_transport(paxos::transport::Unix::new(dir));
+    }
+
+    if let Some(path) = opt.socket {
+        config = config.with_socket(path);
+    }
+
+    if let Some(port) = opt.faults {
+        config = config.with_fault_port(port);
+    }
+
+    if let Some(port) = opt.metrics {
+        config = config.with_metrics_port(port);
+    }
+
+    if let Some(port) = opt.admin {
+        config = config.with_admin_port(port);
+    }
+
+    if let Some(dir) = opt.storage {
+        config = config.with_storage(dir);
+    }
+
+    if opt.trace {
+        config = config.with_tracing();
+    }
+
+    if let Some(cluster) = opt.cluster {
+        config = config.with_cluster(cluster);
+    }
+
+    if let Some(bytes) = opt.max_frame {
+        config = config.with_max_frame(bytes);
+    }
+
+    if let Some(count) = opt.max_malformed {
+        config = config.with_max_malformed(count);
+    }
+
+    if let Some(compression) = opt.compression {
+        config = config.with_compression(compression, opt.compression_threshold);
+    }
+
+    if let Some(path) = opt.secret {
+        match std::fs::read(&path) {
+        | Ok(secret) => config = config.with_secret(secret),
+        | Err(error) => {
+            eprintln!("[ERROR]: could not read secret file {}: {}", path.display(), error);
+            std::process::exit(1);
+        }
+        }
+    }
+
+    if let Some(path) = opt.tokens {
+        match chatroom::Tokens::load(&path) {
+        | Ok(tokens) => config = config.with_auth(tokens),
+        | Err(error) => {
+            eprintln!("[ERROR]: could not load token file {}: {}", path.display(), error);
+            std::process::exit(1);
+        }
+        }
+    }
+
+    match (opt.cert, opt.key, opt.ca) {
+    | (Some(cert), Some(key), Some(ca)) => {
+        match paxos::tls::Tls::new(cert, key, ca, &opt.domain) {
+        | Ok(tls) => config = config.with_tls(tls),
+        | Err(error) => {
+            eprintln!("[ERROR]: {}", error);
+            std::process::exit(1);
+        }
+        }
+    }
+    | (None, None, None) => (),
+    | _ => {
+        eprintln!("[ERROR]: TLS requires --cert, --key, and --ca");
+        std::process::exit(1);
+    }
+    }
+
     tokio::run_async(config.run());
 }
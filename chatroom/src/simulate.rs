@@ -0,0 +1,104 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "chatroom-simulate")]
+struct Opt {
+    /// Seed for all scheduling decisions
+    #[structopt(short = "s", long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Total number of servers
+    #[structopt(short = "c", long = "count", default_value = "3")]
+    count: usize,
+
+    /// Number of client operations to perform
+    #[structopt(short = "n", long = "operations", default_value = "100")]
+    operations: usize,
+
+    /// Probability of dropping each peer message
+    #[structopt(short = "d", long = "drop", default_value = "0.0")]
+    drop_rate: f64,
+
+    /// Timeout between servers (in milliseconds)
+    #[structopt(short = "t", long = "timeout", default_value = "1000")]
+    timeout: u64,
+
+    /// Logging level
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbosity: u8,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let level = match opt.verbosity {
+    | 0 => log::LevelFilter::Off,
+    | 1 => log::LevelFilter::Info,
+    | 2 => log::LevelFilter::Debug,
+    | _ => log::LevelFilter::Trace,
+    };
+
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "[{}][{}]: {}",
+                record.level(),
+                record.target(),
+                message
+            ))
+        })
+        .level_for("paxos", level)
+        .chain(std::io::stdout())
+        .apply()
+        .unwrap();
+
+    let timeout = std::time::Duration::from_millis(opt.timeout);
+    let mut rng = StdRng::seed_from_u64(opt.seed);
+    let mut simulation = paxos::simulate::Simulation::<chatroom::State>::new(opt.seed, opt.count)
+        .with_timeout(timeout)
+        .with_drop_rate(opt.drop_rate);
+
+    // Servers that are currently running
+    let mut running = vec![true; opt.count];
+    for id in 0..opt.count {
+        simulation.start(id);
+    }
+
+    for local_id in 0..opt.operations {
+        let id = rng.gen_range(0, opt.count);
+        match rng.gen_range(0, 10) {
+        | 0 if running[id] && running.iter().filter(|up| **up).count() > opt.count / 2 + 1 => {
+            println!("Crashing server {}", id);
+            simulation.crash(id);
+            running[id] = false;
+        }
+        | 0 | 1 if !running[id] => {
+            println!("Restarting server {}", id);
+            simulation.start(id);
+            running[id] = true;
+        }
+        | 2 | 3 | 4 => {
+            let command = chatroom::Command { client_id: id, local_id, mode: chatroom::Mode::Get };
+            simulation.request(id, command);
+        }
+        | _ => {
+            let message = local_id.to_string();
+            let command = chatroom::Command { client_id: id, local_id, mode: chatroom::Mode::Put(message) };
+            simulation.request(id, command);
+        }
+        }
+        simulation.run_for(timeout / 10);
+    }
+
+    // Let the cluster quiesce
+    for id in 0..opt.count {
+        simulation.start(id);
+    }
+    simulation.run_for(timeout * 10);
+
+    for (id, response) in simulation.responses() {
+        let chatroom::Response::Messages(messages) = response;
+        println!("Client {} received message log {:?}", id, messages);
+    }
+}
@@ -0,0 +1,359 @@
+#![feature(await_macro, async_await, futures_api, pin)]
+
+#[macro_use]
+extern crate tokio;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use serde_derive::{Serialize, Deserialize};
+use structopt::StructOpt;
+use tokio::prelude::*;
+
+use paxos::transport::Transport;
+
+/// Time to wait for a leader to be elected before starting the workload
+const STARTUP: time::Duration = time::Duration::from_secs(2);
+
+#[derive(StructOpt)]
+#[structopt(name = "chatroom-bench")]
+struct Opt {
+    /// Total number of servers
+    #[structopt(short = "c", long = "count", default_value = "3")]
+    count: usize,
+
+    /// Port of server 0; server `i` listens for clients on `PORT + i`
+    #[structopt(short = "p", long = "port", default_value = "10000")]
+    port: usize,
+
+    /// Number of concurrent clients
+    #[structopt(short = "n", long = "clients", default_value = "10")]
+    clients: usize,
+
+    /// Length of the workload (in seconds)
+    #[structopt(short = "d", long = "duration", default_value = "10")]
+    duration: u64,
+
+    /// Total requests per second across all clients. If provided, clients send requests
+    /// at a fixed rate (open loop); otherwise each client waits for a response before
+    /// sending its next request (closed loop).
+    #[structopt(short = "r", long = "rate")]
+    rate: Option<u64>,
+
+    /// State machine to replicate: `counter` or `chatroom`
+    #[structopt(short = "m", long = "machine", default_value = "counter")]
+    machine: Machine,
+
+    /// Timeout between servers (in milliseconds)
+    #[structopt(short = "t", long = "timeout", default_value = "1000")]
+    timeout: u64,
+
+    /// Connect servers and clients over Unix domain sockets in this directory instead of TCP
+    #[structopt(long = "unix", parse(from_os_str))]
+    unix: Option<std::path::PathBuf>,
+
+    /// Logging level
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbosity: u8,
+}
+
+/// Replicated state machine to benchmark.
+#[derive(Copy, Clone, Debug)]
+enum Machine {
+    Counter,
+    Chatroom,
+}
+
+impl std::str::FromStr for Machine {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+        | "counter" => Ok(Machine::Counter),
+        | "chatroom" => Ok(Machine::Chatroom),
+        | _ => Err(format!("unknown state machine {}", s)),
+        }
+    }
+}
+
+/// State machine that can generate its own benchmark requests.
+trait Workload: paxos::State {
+    /// Build the `local_id`-th request for client `client_id`. Every request
+    /// must produce a response, so that its latency can be measured.
+    fn request(client_id: usize, local_id: usize) -> Self::Command;
+}
+
+/// Trivial state machine that counts the commands it has executed.
+#[derive(Clone, Debug, Default)]
+struct Counter(u64);
+
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+struct Increment {
+    client_id: usize,
+    local_id: usize,
+}
+
+impl paxos::Command for Increment {
+    type ClientID = usize;
+    type LocalID = usize;
+    fn client_id(&self) -> Self::ClientID {
+        self.client_id
+    }
+    fn local_id(&self) -> Self::LocalID {
+        self.local_id
+    }
+}
+
+impl paxos::State for Counter {
+    type Command = Increment;
+    type Response = u64;
+    fn execute(&mut self, _: usize, _: Self::Command) -> Option<Self::Response> {
+        self.0 += 1;
+        Some(self.0)
+    }
+    fn tag() -> &'static str {
+        "counter"
+    }
+}
+
+impl Workload for Counter {
+    fn request(client_id: usize, local_id: usize) -> Self::Command {
+        Increment { client_id, local_id }
+    }
+}
+
+/// Since chatroom never acknowledges a `Put`, the chatroom workload only sends `Get`s,
+/// which are still ordered and executed through Paxos like any other command.
+impl Workload for chatroom::State {
+    fn request(client_id: usize, local_id: usize) -> Self::Command {
+        chatroom::Command { client_id, local_id, mode: chatroom::Mode::Get }
+    }
+}
+
+/// Launch an in-process cluster storing its files in `storage`, drive the
+/// workload against it, and report results.
+async fn run<S>(opt: Opt, storage: std::path::PathBuf) where S: Workload, S::Response: serde::de::DeserializeOwned {
+    let timeout = time::Duration::from_millis(opt.timeout);
+    let duration = time::Duration::from_secs(opt.duration);
+    let cluster = paxos::Cluster::new_v4();
+    let transport: Arc<dyn Transport> = match &opt.unix {
+    | Some(dir) => Arc::new(paxos::transport::Unix::new(dir)),
+    | None => Arc::new(paxos::transport::Tcp),
+    };
+
+    for id in 0..opt.count {
+        let config = paxos::Config::<S>::new(id, opt.port + id, opt.count)
+            .with_transport(transport.clone())
+            .with_cluster(cluster)
+            .with_storage(&storage)
+            .with_timeout(timeout);
+        tokio::spawn_async(config.run());
+    }
+
+    await!(tokio::timer::Delay::new(time::Instant::now() + STARTUP)).ok();
+
+    let start = time::Instant::now();
+    let deadline = start + duration;
+    let (results_tx, results_rx) = futures::sync::mpsc::unbounded();
+
+    // Spread clients evenly across servers
+    for client_id in 0..opt.clients {
+        let port = opt.port + client_id % opt.count;
+        let results_tx = results_tx.clone();
+        let transport = transport.clone();
+        let period = opt.rate.map(|rate| {
+            time::Duration::from_nanos(1_000_000_000 * opt.clients as u64 / rate.max(1))
+        });
+        tokio::spawn_async(async move {
+            let latencies = match period {
+            | Some(period) => await!(open::<S>(client_id, cluster, transport, port, deadline, timeout, period)),
+            | None => await!(closed::<S>(client_id, cluster, transport, port, deadline, timeout)),
+            };
+            results_tx.unbounded_send(latencies).ok();
+        });
+    }
+
+    std::mem::drop(results_tx);
+
+    let mut latencies = await!(results_rx.collect())
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    latencies.sort();
+
+    let elapsed = start.elapsed();
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    println!(
+        "Completed {} requests from {} clients in {:?} ({})",
+        latencies.len(),
+        opt.clients,
+        elapsed,
+        if opt.rate.is_some() { "open loop" } else { "closed loop" },
+    );
+    println!("Throughput: {:.1} commits/s", latencies.len() as f64 / seconds);
+    println!(
+        "Latency: p50 {:?}, p99 {:?}, p999 {:?}, max {:?}",
+        harness::percentile(&latencies, 0.5),
+        harness::percentile(&latencies, 0.99),
+        harness::percentile(&latencies, 0.999),
+        harness::percentile(&latencies, 1.0),
+    );
+}
+
+/// Send requests one at a time until `deadline`, waiting for each response.
+async fn closed<S>(
+    client_id: usize,
+    cluster: paxos::Cluster,
+    transport: Arc<dyn Transport>,
+    port: usize,
+    deadline: time::Instant,
+    timeout: time::Duration,
+) -> Vec<time::Duration> where S: Workload, S::Response: serde::de::DeserializeOwned {
+    let mut latencies = Vec::new();
+    let (mut rx, mut tx) = match await!(connect::<S>(cluster, transport, port)) {
+    | Some(channels) => channels,
+    | None => return latencies,
+    };
+
+    let mut local_id = 0;
+    while time::Instant::now() < deadline {
+        let start = time::Instant::now();
+        let writer = &mut tx;
+        if await!(writer.send(S::request(client_id, local_id))).is_err() { break }
+        local_id += 1;
+
+        // Give up on unresponsive servers instead of stalling the benchmark
+        match await!(tokio::timer::Timeout::new(rx.into_future(), timeout * 10)) {
+        | Ok((Some(_), next)) => {
+            latencies.push(start.elapsed());
+            rx = next;
+        }
+        | _ => break,
+        }
+    }
+    latencies
+}
+
+/// Send requests every `period` until `deadline`, regardless of responses.
+/// Responses are matched to requests in the order the requests were sent.
+async fn open<S>(
+    client_id: usize,
+    cluster: paxos::Cluster,
+    transport: Arc<dyn Transport>,
+    port: usize,
+    deadline: time::Instant,
+    timeout: time::Duration,
+    period: time::Duration,
+) -> Vec<time::Duration> where S: Workload, S::Response: serde::de::DeserializeOwned {
+    let mut latencies = Vec::new();
+    let (mut rx, mut tx) = match await!(connect::<S>(cluster, transport, port)) {
+    | Some(channels) => channels,
+    | None => return latencies,
+    };
+
+    // Send times of outstanding requests
+    let sent: Arc<Mutex<VecDeque<time::Instant>>> = Arc::default();
+
+    let outstanding = sent.clone();
+    tokio::spawn_async(async move {
+        let mut next = time::Instant::now();
+        let mut local_id = 0;
+        while next < deadline {
+            await!(tokio::timer::Delay::new(next)).ok();
+            outstanding.lock().unwrap().push_back(time::Instant::now());
+            let writer = &mut tx;
+            if await!(writer.send(S::request(client_id, local_id))).is_err() { break }
+            local_id += 1;
+            next += period;
+        }
+    });
+
+    // Allow outstanding requests to finish after the deadline
+    let drain = deadline + timeout;
+    while time::Instant::now() < drain {
+        let remaining = drain - time::Instant::now();
+        match await!(tokio::timer::Timeout::new(rx.into_future(), remaining)) {
+        | Ok((Some(_), next)) => {
+            if let Some(start) = sent.lock().unwrap().pop_front() {
+                latencies.push(start.elapsed());
+            }
+            rx = next;
+        }
+        | _ => break,
+        }
+    }
+    latencies
+}
+
+/// Connect to the server in `cluster` listening on `port` over `transport`.
+async fn connect<S>(cluster: paxos::Cluster, transport: Arc<dyn Transport>, port: usize) -> Option<(paxos::external::Rx<S::Response>, paxos::external::Tx<S::Command>)>
+    where S: Workload, S::Response: serde::de::DeserializeOwned
+{
+    let stream = match await!(transport.connect(port)) {
+    | Ok(stream) => stream,
+    | Err(_) => {
+        println!("[ERROR]: failed to connect to server at port {}", port);
+        return None
+    }
+    };
+
+    match await!(paxos::handshake::connect::<S, _>(stream, cluster, paxos::Codec::Bincode, Vec::new())) {
+    | Ok(stream) => Some(paxos::external::new(stream)),
+    | Err(error) => {
+        println!("[ERROR]: failed to negotiate with server at port {}: {}", port, error);
+        None
+    }
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let level = match opt.verbosity {
+    | 0 => log::LevelFilter::Off,
+    | 1 => log::LevelFilter::Info,
+    | 2 => log::LevelFilter::Debug,
+    | _ => log::LevelFilter::Trace,
+    };
+
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "[{}][{}]: {}",
+                record.level(),
+                record.target(),
+                message
+            ))
+        })
+        .level_for("paxos", level)
+        .level_for("tokio_io", log::LevelFilter::Off)
+        .level_for("tokio_threadpool", log::LevelFilter::Off)
+        .level_for("tokio_reactor", log::LevelFilter::Off)
+        .level_for("mio", log::LevelFilter::Off)
+        .chain(std::io::stdout())
+        .apply()
+        .unwrap();
+
+    // Start every benchmark from an empty log in a fresh directory
+    let storage = std::env::temp_dir().join(format!("paxos-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&storage)
+        .expect("[INTERNAL ERROR]: could not create storage directory");
+
+    let bench = storage.clone();
+    let bench = async move {
+        match opt.machine {
+        | Machine::Counter => await!(run::<Counter>(opt, bench)),
+        | Machine::Chatroom => await!(run::<chatroom::State>(opt, bench)),
+        }
+        Ok::<_, ()>(())
+    };
+
+    // Servers never terminate on their own, so drop them once the workload is done
+    let mut runtime = tokio::runtime::Runtime::new()
+        .expect("[INTERNAL ERROR]: could not start runtime");
+    runtime.block_on(tokio_async_await::compat::backward::Compat::new(bench)).ok();
+    runtime.shutdown_now().wait().ok();
+    std::fs::remove_dir_all(&storage).ok();
+}
@@ -0,0 +1,231 @@
+//! # Summary
+//!
+//! This module defines independent clients that run their own streams of
+//! commands concurrently with each other. Each client has a unique client ID
+//! and its own connections, so replicas route responses to the right client.
+//!
+//! Clients record their operations in a forked `History`, so that concurrent
+//! requests are still checked for consistency, and time every request that
+//! receives a response. Since `Put` requests are not necessarily acknowledged,
+//! only `Get` requests are timed.
+
+use std::collections::HashMap as Map;
+use std::sync::Arc;
+use std::time;
+
+use paxos::transport::Transport;
+use tokio::prelude::*;
+
+use crate::command::Command;
+use crate::history::{History, Operation};
+use crate::machine::Machine;
+
+/// Time to wait for a response before giving up on a connection
+const RESPONSE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Single client running its own stream of commands against state machine `S`.
+pub struct Client<S: Machine> {
+    /// Unique client ID
+    client_id: usize,
+
+    /// Cluster the servers belong to
+    cluster: paxos::Cluster,
+
+    /// Transport that servers are reached over
+    transport: Arc<dyn Transport>,
+
+    /// Local command identifier
+    counter: usize,
+
+    /// Server ports
+    ports: Map<usize, usize>,
+
+    /// Negotiated connections
+    connections: Map<usize, Connection<S>>,
+
+    /// Client-visible operations
+    history: History,
+
+    /// Time taken by each completed request
+    latencies: Latencies,
+
+    _marker: std::marker::PhantomData<S>,
+}
+
+impl<S> Client<S> where S: Machine, S::Response: serde::de::DeserializeOwned {
+    pub fn new(
+        client_id: usize,
+        cluster: paxos::Cluster,
+        transport: Arc<dyn Transport>,
+        ports: Map<usize, usize>,
+        history: History,
+    ) -> Self {
+        Client {
+            client_id,
+            cluster,
+            transport,
+            counter: 0,
+            ports,
+            connections: Map::default(),
+            history,
+            latencies: Latencies::default(),
+            _marker: Default::default(),
+        }
+    }
+
+    /// Execute `commands` in order, returning the recorded history and latencies.
+    pub async fn run(mut self, commands: Vec<Command>) -> (History, Latencies) {
+        for command in commands {
+            match command {
+            | Command::Connect { id } => {
+                match await!(Connection::open(self.transport.clone(), self.cluster, self.ports[&id])) {
+                | Some(connection) => { self.connections.insert(id, connection); }
+                | None => println!("Client {} could not connect to replica {}", self.client_id, id),
+                }
+            }
+            | Command::Disconnect { id } => {
+                self.connections.remove(&id);
+            }
+            | Command::Get { id } => {
+                let command = S::get(self.client_id, self.counter);
+                self.counter += 1;
+                let start = time::Instant::now();
+                let index = self.history.invoke(id, Operation::Get);
+                if let Some(observation) = await!(self.connection(id).get(command)) {
+                    self.latencies.record(start.elapsed());
+                    self.history.respond::<S>(index, &observation);
+                }
+            }
+            | Command::Put { id, message } => {
+                let command = S::put(self.client_id, self.counter, message.clone());
+                self.counter += 1;
+                self.history.invoke(id, Operation::Put(S::logged(&message)));
+                await!(self.connection(id).put(command));
+            }
+            | Command::Sleep { ms } => {
+                let delay = tokio::timer::Delay::new(time::Instant::now() + time::Duration::from_millis(ms));
+                await!(delay).ok();
+            }
+            | command => {
+                println!("Client {} skipping {:?}: only client commands can run in parallel", self.client_id, command);
+            }
+            }
+        }
+        (self.history, self.latencies)
+    }
+
+    /// Connection to replica `id`, which must have been opened by an earlier `Connect`.
+    fn connection(&mut self, id: usize) -> &mut Connection<S> {
+        self.connections.get_mut(&id).expect("[INTERNAL ERROR]: not connected to replica")
+    }
+}
+
+/// Negotiated connection to a single replica.
+pub struct Connection<S: Machine> {
+    /// Receiving channel, which is only taken while waiting for a response
+    rx: Option<paxos::external::Rx<S::Response>>,
+
+    /// Transmitting channel
+    tx: paxos::external::Tx<S::Command>,
+}
+
+impl<S> Connection<S> where S: Machine, S::Response: serde::de::DeserializeOwned {
+    /// Connect to the replica in `cluster` listening for clients on `port` over
+    /// `transport`, and negotiate the connection.
+    pub async fn open(transport: Arc<dyn Transport>, cluster: paxos::Cluster, port: usize) -> Option<Self> {
+        let stream = await!(transport.connect(port)).ok()?;
+        let handshake = paxos::handshake::connect::<S, _>(stream, cluster, paxos::Codec::Bincode, Vec::new());
+        match await!(handshake) {
+        | Ok(stream) => {
+            let (rx, tx) = paxos::external::new(stream);
+            Some(Connection { rx: Some(rx), tx })
+        }
+        | Err(error) => {
+            println!("Could not negotiate with replica: {}", error);
+            None
+        }
+        }
+    }
+
+    /// Send a GET request, and wait for the replica's observed state. Closes the
+    /// connection if the replica doesn't respond within `RESPONSE_TIMEOUT`, since
+    /// a late response would otherwise be mistaken for the next one.
+    pub async fn get(&mut self, command: S::Command) -> Option<serde_json::Value> {
+        await!(self.put(command));
+        let response = tokio::timer::Timeout::new(self.rx.take()?.into_future(), RESPONSE_TIMEOUT);
+        match await!(response) {
+        | Ok((response, rx)) => {
+            self.rx = Some(rx);
+            response.map(S::observe)
+        }
+        | Err(error) => {
+            self.rx = error.into_inner().map(|(_, rx)| rx);
+            None
+        }
+        }
+    }
+
+    /// Send a request without waiting for a response.
+    pub async fn put(&mut self, command: S::Command) {
+        let writer = &mut self.tx;
+        let _ = await!(writer.send(command));
+    }
+}
+
+/// Latency below which fraction `p` of the sorted `latencies` completed.
+/// Returns zero if no latencies were recorded.
+pub fn percentile(latencies: &[time::Duration], p: f64) -> time::Duration {
+    if latencies.is_empty() { return time::Duration::default() }
+    let index = ((latencies.len() as f64 * p).ceil() as usize).max(1) - 1;
+    latencies[index.min(latencies.len() - 1)]
+}
+
+/// Collection of request latencies.
+#[derive(Clone, Debug, Default)]
+pub struct Latencies(Vec<time::Duration>);
+
+impl Latencies {
+    /// Record the latency of a single request.
+    pub fn record(&mut self, latency: time::Duration) {
+        self.0.push(latency);
+    }
+
+    /// Merge the latencies recorded by another client.
+    pub fn extend(&mut self, other: Latencies) {
+        self.0.extend(other.0);
+    }
+
+    /// Number of recorded requests.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Latency below which fraction `p` of requests completed.
+    /// Returns zero if no requests were recorded.
+    pub fn percentile(&self, p: f64) -> time::Duration {
+        let mut sorted = self.0.clone();
+        sorted.sort();
+        percentile(&sorted, p)
+    }
+
+    /// Average latency, or zero if no requests were recorded.
+    pub fn mean(&self) -> time::Duration {
+        if self.0.is_empty() { return time::Duration::default() }
+        self.0.iter().sum::<time::Duration>() / self.0.len() as u32
+    }
+}
+
+impl std::fmt::Display for Latencies {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            fmt,
+            "{} requests: mean {:?}, p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            self.len(),
+            self.mean(),
+            self.percentile(0.50),
+            self.percentile(0.90),
+            self.percentile(0.99),
+            self.percentile(1.00),
+        )
+    }
+}
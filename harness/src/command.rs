@@ -1,8 +1,36 @@
+use serde::de::{Deserializer, Error};
 use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize)]
 pub struct Execution(pub Vec<Command>);
 
+/// Rejects commands that can't be executed as written, rather than silently
+/// ignoring some of their fields.
+impl<'de> serde::Deserialize<'de> for Execution {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let commands: Vec<Command> = serde::Deserialize::deserialize(deserializer)?;
+        for command in &commands {
+            if let Command::Restart { id, wipe: true, fault: Some(disk) } = command {
+                let message = format!("Restart of replica {} can't both wipe and apply {:?}", id, disk);
+                return Err(D::Error::custom(message))
+            }
+        }
+        Ok(Execution(commands))
+    }
+}
+
+/// Damage to apply to a replica's stable storage files
+#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug)]
+pub enum Disk {
+    /// Cut each file to half its length
+    Truncate,
+
+    /// Flip bytes in the middle of each file
+    Corrupt,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[derive(Clone, Debug)]
@@ -32,7 +60,7 @@ pub enum Command {
     /// Send a PUT request to the specified replica
     Put {
         id: usize,
-        message: String,
+        message: Value,
     },
 
     /// Crash the specified replica
@@ -40,8 +68,72 @@ pub enum Command {
         id: usize,
     },
 
+    /// Crash the specified replica, wait for it to exit, optionally delete
+    /// or damage (but not both) its stable storage, and then start it again
+    Restart {
+        id: usize,
+        #[serde(default)]
+        wipe: bool,
+        #[serde(default)]
+        fault: Option<Disk>,
+    },
+
+    /// Run each stream of commands concurrently on its own client, and
+    /// wait for all of them to finish. Only `Connect`, `Disconnect`, `Get`,
+    /// `Put`, and `Sleep` are supported within a stream.
+    Parallel {
+        streams: Vec<Vec<Command>>,
+    },
+
     /// Sleep the test harness for `ms` milliseconds
     Sleep {
         ms: u64,
-    }
+    },
+
+    /// Drop all messages sent from replicas in `from` to replicas in `to`,
+    /// and vice versa unless `oneway` is set
+    Partition {
+        from: Vec<usize>,
+        to: Vec<usize>,
+        #[serde(default)]
+        oneway: bool,
+    },
+
+    /// Remove all injected faults from all running replicas
+    Heal,
+
+    /// Delay all messages sent from replicas in `from` to replicas in `to` by `ms` milliseconds
+    Delay {
+        from: Vec<usize>,
+        to: Vec<usize>,
+        ms: u64,
+    },
+
+    /// Drop messages sent from replicas in `from` to replicas in `to` with probability `rate`
+    DropRate {
+        from: Vec<usize>,
+        to: Vec<usize>,
+        rate: f64,
+    },
+
+    /// Send a GET request to the specified replica, and fail
+    /// unless its observation is exactly `messages`
+    ExpectGet {
+        id: usize,
+        messages: Value,
+    },
+
+    /// Poll the specified replica with GET requests, and fail unless
+    /// `message` appears in its log within `ms` milliseconds
+    ExpectPut {
+        id: usize,
+        message: Value,
+        ms: u64,
+    },
+
+    /// Poll all connected replicas with GET requests, and fail unless
+    /// they return identical observations within `ms` milliseconds
+    ExpectConverge {
+        ms: u64,
+    },
 }
@@ -0,0 +1,201 @@
+//! # Summary
+//!
+//! This module generates random test executions from a seed. Executions
+//! interleave client requests with crashes, restarts, and partitions, and
+//! always end by healing the network, restarting every replica, and
+//! asserting that all replicas converge.
+//!
+//! Since the harness blocks on every `Get`, reads are only sent to replicas
+//! that can currently reach a majority of running replicas. Replicas are only
+//! started or restarted while the network is whole, since a new process
+//! doesn't inherit the partition injected into the one it replaces.
+
+use std::collections::HashSet as Set;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::command::{Command, Execution};
+
+/// Port of replica 0; replica `i` listens for clients on `BASE_PORT + i`
+const BASE_PORT: usize = 10000;
+
+/// Time to wait for replicas to start listening (in milliseconds)
+const STARTUP: u64 = 1000;
+
+/// Time to wait for replicas to converge at the end of an execution (in milliseconds)
+const CONVERGE: u64 = 20000;
+
+/// Tracks which replicas are running and reachable while generating an execution.
+struct Generator {
+    rng: StdRng,
+    count: usize,
+    commands: Vec<Command>,
+
+    /// Running replicas
+    running: Set<usize>,
+
+    /// Replicas on the minority side of the current partition
+    partitioned: Option<Set<usize>>,
+
+    /// Next unique message to put
+    message: usize,
+}
+
+/// Generate a random execution of `length` steps against `count` replicas.
+pub fn generate(seed: u64, count: usize, length: usize) -> Execution {
+    let mut generator = Generator {
+        rng: StdRng::seed_from_u64(seed),
+        count,
+        commands: Vec::new(),
+        running: Set::default(),
+        partitioned: None,
+        message: 0,
+    };
+
+    for id in 0..count {
+        generator.start(id);
+    }
+
+    for _ in 0..length {
+        generator.step();
+    }
+
+    generator.finish();
+    Execution(generator.commands)
+}
+
+impl Generator {
+
+    /// Majority of all replicas
+    fn majority(&self) -> usize {
+        self.count / 2 + 1
+    }
+
+    /// Whether replica `id` can reach a majority of running replicas.
+    fn available(&self, id: usize) -> bool {
+        let side = self.running.iter()
+            .filter(|other| match &self.partitioned {
+            | Some(minority) => minority.contains(&id) == minority.contains(other),
+            | None => true,
+            })
+            .count();
+        self.running.contains(&id) && side >= self.majority()
+    }
+
+    /// Pick a random element of `ids`, if there is one.
+    fn choose(&mut self, mut ids: Vec<usize>) -> Option<usize> {
+        ids.sort();
+        if ids.is_empty() { return None }
+        let index = self.rng.gen_range(0, ids.len());
+        Some(ids[index])
+    }
+
+    /// Start replica `id` and connect to it once it is listening.
+    fn start(&mut self, id: usize) {
+        self.commands.push(Command::Start { id, port: BASE_PORT + id, count: self.count });
+        self.commands.push(Command::Sleep { ms: STARTUP });
+        self.commands.push(Command::Connect { id });
+        self.running.insert(id);
+    }
+
+    /// Generate a single random command.
+    fn step(&mut self) {
+        let running = self.running.iter().cloned().collect::<Vec<_>>();
+        let stopped = (0..self.count).filter(|id| !self.running.contains(id)).collect::<Vec<_>>();
+        let available = running.iter().cloned().filter(|id| self.available(*id)).collect::<Vec<_>>();
+
+        match self.rng.gen_range(0, 20) {
+        | 0 ..= 7 => if let Some(id) = self.choose(running) {
+            let message = self.message.to_string().into();
+            self.message += 1;
+            self.commands.push(Command::Put { id, message });
+        }
+        | 8 ..= 11 => if let Some(id) = self.choose(available) {
+            self.commands.push(Command::Get { id });
+        }
+        | 12 => if self.running.len() > self.majority() {
+            if let Some(id) = self.choose(running) {
+                self.commands.push(Command::Crash { id });
+                self.running.remove(&id);
+            }
+        }
+        | 13 => if self.partitioned.is_none() {
+            if let Some(id) = self.choose(stopped) {
+                self.start(id);
+            }
+        }
+        | 14 => if self.partitioned.is_none() {
+            if let Some(id) = self.choose(running) {
+                self.commands.push(Command::Restart { id, wipe: false, fault: None });
+                self.commands.push(Command::Sleep { ms: STARTUP });
+                self.commands.push(Command::Connect { id });
+            }
+        }
+        | 15 => if self.partitioned.is_none() && self.count > 1 {
+            let size = self.rng.gen_range(1, self.count / 2 + 1);
+            let mut ids = (0..self.count).collect::<Vec<_>>();
+            let mut minority = Set::default();
+            for _ in 0..size {
+                let index = self.rng.gen_range(0, ids.len());
+                minority.insert(ids.swap_remove(index));
+            }
+            let mut from = minority.iter().cloned().collect::<Vec<_>>();
+            from.sort();
+            ids.sort();
+            self.commands.push(Command::Partition { from, to: ids, oneway: false });
+            self.partitioned = Some(minority);
+        }
+        | 16 => if self.partitioned.is_some() {
+            self.commands.push(Command::Heal);
+            self.partitioned = None;
+        }
+        | _ => {
+            let ms = self.rng.gen_range(10, 500);
+            self.commands.push(Command::Sleep { ms });
+        }
+        }
+    }
+
+    /// Heal the network, restart all replicas, and check for convergence.
+    fn finish(&mut self) {
+        if self.partitioned.take().is_some() {
+            self.commands.push(Command::Heal);
+        }
+        for id in 0..self.count {
+            if !self.running.contains(&id) {
+                self.start(id);
+            }
+        }
+        self.commands.push(Command::ExpectConverge { ms: CONVERGE });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_generates_same_execution() {
+        let a = serde_json::to_string(&generate(7, 5, 200)).unwrap();
+        let b = serde_json::to_string(&generate(7, 5, 200)).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn never_starts_replicas_while_partitioned() {
+        for seed in 0..50 {
+            let mut partitioned = false;
+            for command in generate(seed, 5, 200).0 {
+                match command {
+                | Command::Partition { .. } => partitioned = true,
+                | Command::Heal => partitioned = false,
+                | Command::Start { id, .. } | Command::Restart { id, .. } => {
+                    assert!(!partitioned, "seed {} started replica {} while partitioned", seed, id);
+                }
+                | _ => (),
+                }
+            }
+        }
+    }
+}
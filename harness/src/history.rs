@@ -0,0 +1,285 @@
+//! # Summary
+//!
+//! This module records a timestamped history of client invocations and
+//! responses, and checks that it is consistent with a single linearizable
+//! message log.
+//!
+//! Since `Put` requests are never acknowledged, the check is phrased in terms
+//! of the logs returned by `Get` requests:
+//!
+//! - Every message in a log must have been put before the `Get` returned,
+//!   and must appear at most as many times as it was put
+//! - Any two logs must be prefixes of one global sequence
+//! - If one `Get` returns before another is invoked, then the earlier
+//!   log must be a prefix of the later one
+//!
+//! Messages are compared as JSON values, with each `Put` recorded as the element
+//! it appears as in logs, according to `Machine::logged`. The check is skipped
+//! for state machines whose observations are not logs.
+
+use std::time;
+
+use serde_json::Value;
+
+use crate::machine::Machine;
+
+/// Client-visible operation.
+#[derive(Clone, Debug)]
+pub enum Operation {
+    Get,
+    Put(Value),
+}
+
+/// Single completed or outstanding request.
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Replica the request was sent to
+    pub id: usize,
+
+    /// Requested operation
+    pub operation: Operation,
+
+    /// Time the request was sent
+    pub invoked: time::Duration,
+
+    /// Time and contents of the response, if one was received
+    pub response: Option<(time::Duration, Vec<Value>)>,
+}
+
+/// Minimal set of events that cannot be explained by any linearization.
+#[derive(Debug)]
+pub enum Violation {
+    /// A log contains a message that was never put, or was put fewer times
+    Phantom { event: Event, message: Value },
+
+    /// A log contains a message that was put only after the `Get` returned
+    Future { get: Event, put: Event },
+
+    /// Two logs disagree at some index
+    Diverged { left: Event, right: Event, index: usize },
+
+    /// A later `Get` observed fewer messages than an earlier `Get`
+    Stale { earlier: Event, later: Event },
+}
+
+/// Timestamped history of client operations.
+pub struct History {
+    /// Start of the test execution
+    start: time::Instant,
+
+    /// Recorded events in invocation order
+    events: Vec<Event>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            start: time::Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Create an empty history sharing this history's start time, so that
+    /// a concurrent client can record events to be merged back later.
+    pub fn fork(&self) -> Self {
+        History {
+            start: self.start,
+            events: Vec::new(),
+        }
+    }
+
+    /// Merge the events of a forked history back into this one.
+    pub fn merge(&mut self, other: History) {
+        self.events.extend(other.events);
+        self.events.sort_by_key(|event| event.invoked);
+    }
+
+    /// Record that `operation` was sent to replica `id`, returning a handle
+    /// that can later be passed to `respond`.
+    pub fn invoke(&mut self, id: usize, operation: Operation) -> usize {
+        self.events.push(Event {
+            id,
+            operation,
+            invoked: self.start.elapsed(),
+            response: None,
+        });
+        self.events.len() - 1
+    }
+
+    /// Record that the request with handle `index` observed `observation`.
+    /// Observations that are not logs are left out of the consistency check.
+    pub fn respond<S: Machine>(&mut self, index: usize, observation: &Value) {
+        if let Some(log) = S::log(observation) {
+            self.events[index].response = Some((self.start.elapsed(), log));
+        }
+    }
+
+    /// Check this history for consistency, returning the first violation found.
+    pub fn check(&self) -> Result<(), Violation> {
+        let gets = self.events.iter()
+            .filter(|event| event.response.is_some())
+            .collect::<Vec<_>>();
+
+        let puts = self.events.iter()
+            .filter_map(|event| match &event.operation {
+            | Operation::Put(message) => Some((message, event)),
+            | Operation::Get => None,
+            })
+            .collect::<Vec<_>>();
+
+        // Every message must have been put, no later than the `Get` returned
+        for get in &gets {
+            let (returned, log) = get.response.as_ref().unwrap();
+            for (index, message) in log.iter().enumerate() {
+                let count = log[..index].iter().filter(|earlier| *earlier == message).count();
+                let put = match puts.iter().filter(|(put, _)| *put == message).nth(count) {
+                | Some((_, put)) => put,
+                | None => return Err(Violation::Phantom {
+                    event: (*get).clone(),
+                    message: message.clone(),
+                }),
+                };
+                if put.invoked > *returned {
+                    return Err(Violation::Future {
+                        get: (*get).clone(),
+                        put: (*put).clone(),
+                    })
+                }
+            }
+        }
+
+        // All logs must be prefixes of one global sequence
+        for (i, left) in gets.iter().enumerate() {
+            for right in &gets[i + 1..] {
+                let l = &left.response.as_ref().unwrap().1;
+                let r = &right.response.as_ref().unwrap().1;
+                if let Some(index) = l.iter().zip(r).position(|(l, r)| l != r) {
+                    return Err(Violation::Diverged {
+                        left: (*left).clone(),
+                        right: (*right).clone(),
+                        index,
+                    })
+                }
+            }
+        }
+
+        // Logs must grow in real-time order
+        for earlier in &gets {
+            for later in &gets {
+                let (returned, e) = earlier.response.as_ref().unwrap();
+                let (_, l) = later.response.as_ref().unwrap();
+                if *returned < later.invoked && e.len() > l.len() {
+                    return Err(Violation::Stale {
+                        earlier: (*earlier).clone(),
+                        later: (*later).clone(),
+                    })
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{:?} on replica {} invoked at {:?}", self.operation, self.id, self.invoked)?;
+        if let Some((returned, messages)) = &self.response {
+            write!(fmt, " returned {} at {:?}", Value::Array(messages.clone()), returned)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+        | Violation::Phantom { event, message } => {
+            write!(fmt, "message {} was never put, but was read by:\n  {}", message, event)
+        }
+        | Violation::Future { get, put } => {
+            write!(fmt, "read returned before write was invoked:\n  {}\n  {}", get, put)
+        }
+        | Violation::Diverged { left, right, index } => {
+            write!(fmt, "logs diverge at index {}:\n  {}\n  {}", index, left, right)
+        }
+        | Violation::Stale { earlier, later } => {
+            write!(fmt, "later read observed fewer messages:\n  {}\n  {}", earlier, later)
+        }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> time::Duration {
+        time::Duration::from_millis(ms)
+    }
+
+    fn put(message: &str, invoked: u64) -> Event {
+        Event { id: 0, operation: Operation::Put(Value::from(message)), invoked: ms(invoked), response: None }
+    }
+
+    fn get(invoked: u64, returned: u64, log: &[&str]) -> Event {
+        let log = log.iter().map(|message| Value::from(*message)).collect();
+        Event { id: 0, operation: Operation::Get, invoked: ms(invoked), response: Some((ms(returned), log)) }
+    }
+
+    fn check(events: Vec<Event>) -> Result<(), Violation> {
+        History { start: time::Instant::now(), events }.check()
+    }
+
+    #[test]
+    fn accepts_concurrent_linearizable_history() {
+        let history = vec![
+            put("a", 0),
+            put("b", 0),
+            get(1, 5, &["b"]),
+            get(2, 4, &[]),
+            get(6, 7, &["b", "a"]),
+        ];
+        assert!(check(history).is_ok());
+    }
+
+    #[test]
+    fn rejects_messages_never_put() {
+        match check(vec![get(0, 1, &["a"])]) {
+        | Err(Violation::Phantom { message, .. }) => assert_eq!(message, Value::from("a")),
+        | other => panic!("expected phantom message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_messages_read_more_often_than_put() {
+        match check(vec![put("a", 0), get(1, 2, &["a", "a"])]) {
+        | Err(Violation::Phantom { message, .. }) => assert_eq!(message, Value::from("a")),
+        | other => panic!("expected phantom message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_messages_put_after_read() {
+        match check(vec![get(0, 5, &["a"]), put("a", 10)]) {
+        | Err(Violation::Future { .. }) => (),
+        | other => panic!("expected read from the future, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_diverging_logs() {
+        match check(vec![put("a", 0), put("b", 0), get(1, 3, &["a", "b"]), get(2, 4, &["b", "a"])]) {
+        | Err(Violation::Diverged { index, .. }) => assert_eq!(index, 0),
+        | other => panic!("expected diverging logs, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_stale_reads() {
+        match check(vec![put("a", 0), put("b", 0), get(1, 2, &["a", "b"]), get(3, 4, &["a"])]) {
+        | Err(Violation::Stale { .. }) => (),
+        | other => panic!("expected stale read, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! # Summary
+//!
+//! This crate runs scripted or randomly generated tests against a cluster of
+//! server processes, injecting crashes, restarts, and network faults, and checks
+//! the client-visible history for consistency. It is generic over the replicated
+//! state machine: any `paxos::State` that implements `Machine` can be tested by
+//! calling `run` with its own server binary.
+
+#![feature(await_macro, async_await, futures_api, pin)]
+
+#[macro_use]
+extern crate tokio;
+
+use std::collections::HashMap as Map;
+use std::sync::Arc;
+
+use structopt::StructOpt;
+use tokio::prelude::*;
+
+use paxos::transport::Transport;
+
+mod client;
+mod command;
+mod generate;
+mod history;
+mod machine;
+mod server;
+
+use crate::client::{Client, Connection, Latencies};
+use crate::command::{Command, Execution};
+use crate::history::{History, Operation};
+use crate::server::{FAULT_PORT, Server};
+
+pub use crate::client::percentile;
+pub use crate::machine::Machine;
+
+/// Time to wait between polling replicas in assertions (in milliseconds)
+const POLL_INTERVAL: u64 = 100;
+
+/// Client ID of the first concurrent client, chosen to avoid replica IDs
+const CLIENT_OFFSET: usize = 1000;
+
+/// Command-line options for a single test run.
+#[derive(StructOpt)]
+#[structopt(name = "harness")]
+pub struct Opt {
+    /// Paxos server binary
+    #[structopt(short = "s", long = "server")]
+    server: std::path::PathBuf,
+
+    /// Test file
+    #[structopt(short = "f", long = "file", required_unless = "seed")]
+    file: Option<std::path::PathBuf>,
+
+    /// Generate a random test from this seed instead of reading a test file
+    #[structopt(long = "seed", conflicts_with = "file")]
+    seed: Option<u64>,
+
+    /// Number of replicas in a generated test
+    #[structopt(long = "count", default_value = "5")]
+    count: usize,
+
+    /// Number of random steps in a generated test
+    #[structopt(long = "length", default_value = "100")]
+    length: usize,
+
+    /// Connect servers and clients over Unix domain sockets in this directory instead of TCP
+    #[structopt(long = "unix", parse(from_os_str))]
+    unix: Option<std::path::PathBuf>,
+
+    /// Logging output verbosity
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: u8,
+}
+
+/// Run the test described by `opt` against servers running state machine `S`,
+/// returning whether every assertion and the consistency check passed.
+pub async fn run<S>(opt: Opt) -> bool where S: Machine, S::Response: serde::de::DeserializeOwned {
+
+    // Test execution
+    let execution: Execution = match (&opt.file, opt.seed) {
+    | (_, Some(seed)) => {
+        println!("Generating test with seed {}", seed);
+        generate::generate(seed, opt.count, opt.length)
+    }
+    | (Some(file), None) => {
+        std::fs::File::open(file)
+            .map(serde_json::from_reader)
+            .expect("[INTERNAL ERROR]: could not find file")
+            .expect("[INTERNAL ERROR]: could not parse test")
+    }
+    | (None, None) => unreachable!(),
+    };
+
+    // Cluster shared by every server in this test, which stores its files in a fresh directory
+    let cluster = paxos::Cluster::new_v4();
+    let storage = std::env::temp_dir().join(format!("paxos-harness-{}", cluster));
+    std::fs::create_dir_all(&storage)
+        .expect("[INTERNAL ERROR]: could not create storage directory");
+
+    // Transport that clients reach servers over
+    let transport: Arc<dyn Transport> = match &opt.unix {
+    | Some(dir) => Arc::new(paxos::transport::Unix::new(dir)),
+    | None => Arc::new(paxos::transport::Tcp),
+    };
+
+    // Negotiated connections
+    let mut connections: Map<usize, Connection<S>> = Map::default();
+
+    // Running servers
+    let mut servers: Map<usize, Server> = Map::default();
+
+    // Server ports
+    let mut ports: Map<usize, usize> = Map::default();
+
+    // Server cluster sizes
+    let mut counts: Map<usize, usize> = Map::default();
+
+    // Local command identifier
+    let mut counter: usize = 0;
+
+    // Client-visible operations
+    let mut history = History::new();
+
+    // Number of concurrent clients started so far
+    let mut clients: usize = 0;
+
+    // Number of failed assertions
+    let mut failures: usize = 0;
+
+    for command in execution.0.iter().cloned() {
+        println!("Executing command {:?}", command);
+        match command {
+        | Command::Start { id, port, count } => {
+            servers.insert(id, Server::new(&opt.server, id, port, count, cluster, &storage, opt.unix.as_ref().map(AsRef::as_ref), opt.verbose));
+            ports.insert(id, port);
+            counts.insert(id, count);
+        }
+        | Command::Connect { id } => {
+            let connection = await!(Connection::open(transport.clone(), cluster, ports[&id]))
+                .expect("[INTERNAL ERROR]: could not connect to server");
+            connections.insert(id, connection);
+        }
+        | Command::Disconnect { id } => {
+            connections.remove(&id);
+        }
+        | Command::Get { id } => {
+            let command = S::get(id, counter);
+            counter += 1;
+            let index = history.invoke(id, Operation::Get);
+            if let Some(observation) = await!(connection(&mut connections, id).get(command)) {
+                println!("Client {} received {}", id, observation);
+                history.respond::<S>(index, &observation);
+            }
+        }
+        | Command::Put { id, message } => {
+            let command = S::put(id, counter, message.clone());
+            counter += 1;
+            history.invoke(id, Operation::Put(S::logged(&message)));
+            await!(connection(&mut connections, id).put(command));
+        }
+        | Command::Parallel { streams } => {
+            let mut done = Vec::new();
+            for commands in streams {
+                let client = Client::<S>::new(CLIENT_OFFSET + clients, cluster, transport.clone(), ports.clone(), history.fork());
+                let (tx, rx) = futures::sync::oneshot::channel();
+                clients += 1;
+                tokio::spawn_async(async move {
+                    let _ = tx.send(await!(client.run(commands)));
+                });
+                done.push(rx);
+            }
+            let mut latencies = Latencies::default();
+            for rx in done {
+                if let Ok((forked, recorded)) = await!(rx) {
+                    history.merge(forked);
+                    latencies.extend(recorded);
+                }
+            }
+            println!("Completed {}", latencies);
+        }
+        | Command::Crash { id } => {
+            servers.remove(&id);
+        }
+        | Command::Restart { id, wipe, fault } => {
+            if let Some(server) = servers.remove(&id) {
+                server.stop();
+            }
+            if wipe {
+                Server::wipe(&storage, id);
+            } else if let Some(disk) = fault {
+                Server::damage(&storage, id, disk);
+            }
+            servers.insert(id, Server::new(&opt.server, id, ports[&id], counts[&id], cluster, &storage, opt.unix.as_ref().map(AsRef::as_ref), opt.verbose));
+        }
+        | Command::Sleep { ms } => {
+            std::thread::sleep(std::time::Duration::from_millis(ms))
+        }
+        | Command::Partition { from, to, oneway } => {
+            for f in &from {
+                for t in &to {
+                    await!(inject(*f, paxos::Fault::Block(*t)));
+                    if !oneway {
+                        await!(inject(*t, paxos::Fault::Block(*f)));
+                    }
+                }
+            }
+        }
+        | Command::Heal => {
+            for id in servers.keys() {
+                await!(inject(*id, paxos::Fault::Heal));
+            }
+        }
+        | Command::Delay { from, to, ms } => {
+            let delay = std::time::Duration::from_millis(ms);
+            for f in &from {
+                for t in &to {
+                    await!(inject(*f, paxos::Fault::Delay(*t, delay)));
+                }
+            }
+        }
+        | Command::DropRate { from, to, rate } => {
+            for f in &from {
+                for t in &to {
+                    await!(inject(*f, paxos::Fault::Drop(*t, rate)));
+                }
+            }
+        }
+        | Command::ExpectGet { id, messages: expected } => {
+            let command = S::get(id, counter);
+            counter += 1;
+            let index = history.invoke(id, Operation::Get);
+            match await!(connection(&mut connections, id).get(command)) {
+            | Some(observation) => {
+                if observation != expected {
+                    println!("Assertion failed: expected {} from replica {}, but received {}", expected, id, observation);
+                    failures += 1;
+                }
+                history.respond::<S>(index, &observation);
+            }
+            | None => {
+                println!("Assertion failed: expected {} from replica {}, but received nothing", expected, id);
+                failures += 1;
+            }
+            }
+        }
+        | Command::ExpectPut { id, message, ms } => {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_millis(ms);
+            let mut acknowledged = false;
+            while !acknowledged && std::time::Instant::now() < deadline {
+                let command = S::get(id, counter);
+                counter += 1;
+                let index = history.invoke(id, Operation::Get);
+                if let Some(observation) = await!(connection(&mut connections, id).get(command)) {
+                    acknowledged = S::log(&observation)
+                        .expect("[INTERNAL ERROR]: ExpectPut requires a log state machine")
+                        .contains(&S::logged(&message));
+                    history.respond::<S>(index, &observation);
+                }
+                if !acknowledged {
+                    std::thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL));
+                }
+            }
+            if !acknowledged {
+                println!("Assertion failed: replica {} did not acknowledge {} within {} ms", id, message, ms);
+                failures += 1;
+            }
+        }
+        | Command::ExpectConverge { ms } => {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_millis(ms);
+            let mut ids = connections.keys()
+                .filter(|id| servers.contains_key(id))
+                .cloned()
+                .collect::<Vec<_>>();
+            ids.sort();
+            let mut logs = Vec::new();
+            loop {
+                logs.clear();
+                for id in &ids {
+                    let command = S::get(*id, counter);
+                    counter += 1;
+                    let index = history.invoke(*id, Operation::Get);
+                    if let Some(observation) = await!(connection(&mut connections, *id).get(command)) {
+                        history.respond::<S>(index, &observation);
+                        logs.push((*id, Some(observation)));
+                    } else {
+                        logs.push((*id, None));
+                    }
+                }
+                let converged = logs.iter().all(|(_, log)| log.is_some() && *log == logs[0].1);
+                if converged || std::time::Instant::now() >= deadline { break }
+                std::thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL));
+            }
+            if logs.iter().any(|(_, log)| log.is_none() || *log != logs[0].1) {
+                println!("Assertion failed: replicas did not converge within {} ms", ms);
+                for (id, log) in &logs {
+                    match log {
+                    | Some(observation) => println!("  Replica {} observed {}", id, observation),
+                    | None => println!("  Replica {} did not respond", id),
+                    }
+                }
+                failures += 1;
+            }
+        }
+        }
+    }
+
+    // Kill servers before checking the history, which can take a while
+    std::mem::drop(servers);
+    std::fs::remove_dir_all(&storage).ok();
+
+    let mut failed = false;
+
+    if let Err(violation) = history.check() {
+        println!("Consistency violation: {}", violation);
+        failed = true;
+    }
+
+    if failures > 0 {
+        println!("{} assertion(s) failed", failures);
+        failed = true;
+    }
+
+    if failed {
+        // Save generated tests so they can be replayed with `--file`
+        if let Some(seed) = opt.seed {
+            let path = format!("schedule-{}.json", seed);
+            std::fs::File::create(&path)
+                .map(|file| serde_json::to_writer_pretty(file, &execution))
+                .expect("[INTERNAL ERROR]: could not create file")
+                .expect("[INTERNAL ERROR]: could not write test");
+            println!("Failed with seed {}, replay with --file {}", seed, path);
+        }
+    }
+
+    !failed
+}
+
+/// Connection to replica `id`, which must have been opened by an earlier `Connect`.
+fn connection<S: Machine>(connections: &mut Map<usize, Connection<S>>, id: usize) -> &mut Connection<S> {
+    connections.get_mut(&id).expect("[INTERNAL ERROR]: not connected to replica")
+}
+
+/// Inject `fault` into the outgoing connections of replica `id`.
+async fn inject(id: usize, fault: paxos::Fault) {
+    let stream = format!("127.0.0.1:{}", id + FAULT_PORT)
+        .parse::<std::net::SocketAddr>()
+        .ok()
+        .and_then(|address| std::net::TcpStream::connect(&address).ok())
+        .and_then(|stream| tokio::net::tcp::TcpStream::from_std(stream, &tokio::reactor::Handle::default()).ok());
+
+    match stream {
+    | Some(stream) => {
+        let (_, tx) = paxos::external::new::<(), paxos::Fault, _>(stream);
+        let _ = await!(tx.send(fault));
+    }
+    | None => println!("Could not inject {:?} into replica {}", fault, id),
+    }
+}
+
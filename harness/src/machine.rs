@@ -0,0 +1,65 @@
+//! # Summary
+//!
+//! This module describes how the harness drives a replicated state machine.
+//! Test files only ever refer to `Get`s, which observe the replicated state,
+//! and `Put`s, which write a JSON value to it. Implementing `Machine` for a
+//! `paxos::State` lets the harness build its commands and compare its responses,
+//! so the same test scripts and fault injection can be reused across state machines.
+//!
+//! State machines whose observations are logs of `Put` values additionally
+//! support `ExpectPut` and the consistency check in the `history` module.
+
+use serde_json::Value;
+
+/// Replicated state machine that can be driven by test files.
+pub trait Machine: paxos::State {
+    /// Build a read-only command that observes the replicated state.
+    fn get(client_id: usize, local_id: usize) -> Self::Command;
+
+    /// Build a command that writes `message`, as provided by a `Put` in a test file.
+    fn put(client_id: usize, local_id: usize, message: Value) -> Self::Command;
+
+    /// Convert the response to a `get` command into JSON, which is compared
+    /// against `ExpectGet` assertions and across replicas.
+    fn observe(response: Self::Response) -> Value;
+
+    /// Sequence of `Put` messages contained in an observation, if the
+    /// state machine is a log. Returns `None` for all other state machines.
+    fn log(observation: &Value) -> Option<Vec<Value>>;
+
+    /// Element of `log` that a `Put` of `message` appears as. Defaults to
+    /// `message` itself, for state machines that store JSON values as is.
+    fn logged(message: &Value) -> Value {
+        message.clone()
+    }
+}
+
+impl Machine for chatroom::State {
+    fn get(client_id: usize, local_id: usize) -> Self::Command {
+        chatroom::Command { client_id, local_id, mode: chatroom::Mode::Get }
+    }
+
+    fn put(client_id: usize, local_id: usize, message: Value) -> Self::Command {
+        let message = match message {
+        | Value::String(message) => message,
+        | message => message.to_string(),
+        };
+        chatroom::Command { client_id, local_id, mode: chatroom::Mode::Put(message) }
+    }
+
+    fn logged(message: &Value) -> Value {
+        match message {
+        | Value::String(_) => message.clone(),
+        | message => Value::String(message.to_string()),
+        }
+    }
+
+    fn observe(response: Self::Response) -> Value {
+        let chatroom::Response::Messages(messages) = response;
+        Value::from(messages)
+    }
+
+    fn log(observation: &Value) -> Option<Vec<Value>> {
+        observation.as_array().cloned()
+    }
+}
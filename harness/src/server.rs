@@ -1,3 +1,11 @@
+use crate::command::Disk;
+
+/// Offset from server ID to fault injection port
+pub const FAULT_PORT: usize = 30000;
+
+/// Number of bytes to flip when corrupting stable storage
+const CORRUPT_BYTES: usize = 8;
+
 pub struct Server(std::process::Child);
 
 impl Server {
@@ -6,25 +14,81 @@ impl Server {
         id: usize,
         port: usize,
         count: usize,
+        cluster: paxos::Cluster,
+        storage: &std::path::Path,
+        unix: Option<&std::path::Path>,
         verbose: u8,
     ) -> Self {
+        let faults = (id + FAULT_PORT).to_string();
         let id = id.to_string();
         let port = port.to_string();
         let count = count.to_string();
+        let cluster = cluster.to_string();
         let mut command = std::process::Command::new(path);
         if verbose > 0 {
             let verbosity = "-".to_string() + &"v".repeat(verbose as usize);
             command.arg(&verbosity);
         }
+        if let Some(dir) = unix {
+            command.arg("--unix").arg(dir);
+        }
         command.args(&["-i", &id])
             .args(&["-p", &port])
             .args(&["-c", &count])
+            .args(&["-f", &faults])
+            .args(&["--cluster", &cluster])
+            .arg("--storage")
+            .arg(storage)
             .spawn()
             .map(Server)
             .expect("[INTERNAL ERROR]: could not spawn server")
     }
 }
 
+impl Server {
+    /// Kill this server and wait for its process to exit, so that
+    /// its stable storage files are no longer being written.
+    pub fn stop(mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+
+    /// Paths to the stable storage files of server `id` in directory `storage`.
+    pub fn storage(storage: &std::path::Path, id: usize) -> Vec<std::path::PathBuf> {
+        ["acceptor", "leader", "replica"].iter()
+            .map(|thread| storage.join(format!("{}-{:>02}.paxos", thread, id)))
+            .collect()
+    }
+
+    /// Delete the stable storage files of server `id` in directory `storage`.
+    pub fn wipe(storage: &std::path::Path, id: usize) {
+        for path in Server::storage(storage, id) {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    /// Apply `disk` damage to the stable storage files of server `id` in directory `storage`.
+    pub fn damage(storage: &std::path::Path, id: usize, disk: Disk) {
+        for path in Server::storage(storage, id) {
+            let mut bytes = match std::fs::read(&path) {
+            | Ok(bytes) => bytes,
+            | Err(_) => continue,
+            };
+            let middle = bytes.len() / 2;
+            match disk {
+            | Disk::Truncate => bytes.truncate(middle),
+            | Disk::Corrupt => {
+                for byte in bytes.iter_mut().skip(middle).take(CORRUPT_BYTES) {
+                    *byte ^= 0xFF;
+                }
+            }
+            }
+            std::fs::write(&path, bytes)
+                .expect("[INTERNAL ERROR]: could not damage stable storage");
+        }
+    }
+}
+
 impl Drop for Server {
     fn drop(&mut self) {
         self.0.kill().ok();
@@ -0,0 +1,26 @@
+//! # Summary
+//!
+//! This module defines the hooks for authenticating clients and authorizing
+//! their requests. When configured with `Config::with_auth`, a server requires
+//! every client to present a credential with `handshake::connect` before sending
+//! any commands, and binds the connection to the client ID it authenticates as.
+//!
+//! Commands whose `client_id` doesn't match the authenticated ID close the
+//! connection, since they could otherwise steal another client's responses.
+//! Commands that fail authorization also close the connection before they reach
+//! the replica, since `State::Response` has no way to report the rejection.
+
+use crate::state;
+
+/// Authenticates clients and authorizes their commands.
+pub trait Auth<C: state::Command>: Send + Sync + 'static {
+    /// Verify the `credential` presented by a connecting client, returning
+    /// the client ID it proves, or `None` to reject the connection.
+    fn authenticate(&self, credential: &[u8]) -> Option<C::ClientID>;
+
+    /// Decide whether authenticated client `client_id` may issue `command`.
+    /// Consulted for every command before it is proposed.
+    fn authorize(&self, _client_id: &C::ClientID, _command: &C) -> bool {
+        true
+    }
+}
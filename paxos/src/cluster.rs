@@ -0,0 +1,52 @@
+//! # Summary
+//!
+//! This module determines which cluster a server belongs to. Since ports are
+//! derived from server IDs, servers from different clusters on the same host
+//! could otherwise connect to each other, or recover from each other's storage.
+//!
+//! Each server persists its cluster ID to `cluster-XX.paxos` in its storage
+//! directory, so that it only needs to be configured on first launch. Every
+//! stable storage file is also stamped with the cluster ID, and checked when
+//! loaded. Servers that were never assigned a cluster refuse to start, rather
+//! than silently joining every other unassigned server.
+
+use std::io::Write;
+
+/// Unique identifier of a cluster.
+pub type Cluster = uuid::Uuid;
+
+/// Determine the cluster that server `id` belongs to from the `configured` cluster
+/// and the cluster persisted in directory `storage` by a previous launch, persisting the result.
+///
+/// Panics if neither is assigned, if the configured cluster is the nil UUID, or if
+/// both are assigned and disagree, since the server would otherwise recover into the
+/// wrong cluster.
+pub(crate) fn resolve(id: usize, storage: &std::path::Path, configured: Option<Cluster>) -> Cluster {
+    let path = storage.join(format!("cluster-{:>02}.paxos", id));
+    let persisted = std::fs::read_to_string(&path)
+        .ok()
+        .map(|cluster| {
+            Cluster::parse_str(cluster.trim())
+                .expect("[CLUSTER ERROR]: could not parse persisted cluster ID")
+        });
+
+    if configured.map_or(false, |cluster| cluster.is_nil()) {
+        panic!("[CLUSTER ERROR]: the nil UUID cannot be used as a cluster ID");
+    }
+
+    let cluster = match (configured, persisted) {
+    | (Some(configured), Some(persisted)) if configured != persisted => {
+        panic!("[CLUSTER ERROR]: server belongs to cluster {}, not {}", persisted, configured)
+    }
+    | (Some(cluster), _) | (None, Some(cluster)) => cluster,
+    | (None, None) => {
+        panic!("[CLUSTER ERROR]: server {} was never assigned a cluster ID, which is required on first launch", id)
+    }
+    };
+
+    std::fs::File::create(&path)
+        .and_then(|mut file| writeln!(file, "{}", cluster))
+        .expect("[CLUSTER ERROR]: could not persist cluster ID");
+
+    cluster
+}
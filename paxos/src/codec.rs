@@ -0,0 +1,248 @@
+//! # Summary
+//!
+//! This module defines the serialization formats that external channels can
+//! encode frames with. `Bincode` is the most compact, and is always used between
+//! servers. `Json` and `MessagePack` are easier to produce from other languages,
+//! and `Json` frames can be read directly when debugging client traffic. Clients
+//! choose their codec when connecting, by sending its `id` in the connection header.
+//!
+//! MessagePack structs are encoded as maps with named fields rather than as
+//! arrays, so that frames are self-describing, like JSON.
+//!
+//! Connections that negotiate a `Compression` algorithm prefix every encoded
+//! frame with a flag byte. Frames at least as long as the connection's threshold
+//! are compressed, and also carry their uncompressed length, which is checked
+//! against the maximum frame size before decompressing. Smaller frames, and
+//! frames that don't shrink, are sent as is.
+
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+
+/// Serialization format for frames on an external channel.
+#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Bincode,
+    Json,
+    MessagePack,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Bincode
+    }
+}
+
+/// Algorithm for compressing large frames.
+#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Lz4,
+    Zstd,
+}
+
+/// Flag for frames sent as is
+const RAW: u8 = 0;
+
+/// Flag for compressed frames
+const COMPRESSED: u8 = 1;
+
+/// Compression level for zstd, which favors speed over ratio
+const ZSTD_LEVEL: i32 = 1;
+
+/// Failure to encode or decode a frame.
+#[derive(Debug)]
+pub enum Error {
+    Bincode(bincode::Error),
+    Json(serde_json::Error),
+    Encode(rmp_serde::encode::Error),
+    Decode(rmp_serde::decode::Error),
+    Compress(std::io::Error),
+    Decompress(std::io::Error),
+    Flag(u8),
+    Truncated,
+    Oversized(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+        | Error::Bincode(error) => write!(fmt, "invalid bincode: {}", error),
+        | Error::Json(error) => write!(fmt, "invalid JSON: {}", error),
+        | Error::Encode(error) => write!(fmt, "could not encode MessagePack: {}", error),
+        | Error::Decode(error) => write!(fmt, "invalid MessagePack: {}", error),
+        | Error::Compress(error) => write!(fmt, "could not compress frame: {}", error),
+        | Error::Decompress(error) => write!(fmt, "could not decompress frame: {}", error),
+        | Error::Flag(flag) => write!(fmt, "invalid compression flag {}", flag),
+        | Error::Truncated => write!(fmt, "truncated compressed frame"),
+        | Error::Oversized(len) => write!(fmt, "frame of {} bytes is too large", len),
+        }
+    }
+}
+
+impl Codec {
+    /// Encode `message` as a single frame.
+    pub fn encode<T: serde::Serialize>(&self, message: &T) -> Result<Vec<u8>, Error> {
+        match self {
+        | Codec::Bincode => bincode::serialize(message).map_err(Error::Bincode),
+        | Codec::Json => serde_json::to_vec(message).map_err(Error::Json),
+        | Codec::MessagePack => rmp_serde::to_vec_named(message).map_err(Error::Encode),
+        }
+    }
+
+    /// Decode a single frame.
+    pub fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> Result<T, Error> {
+        match self {
+        | Codec::Bincode => bincode::deserialize(frame).map_err(Error::Bincode),
+        | Codec::Json => serde_json::from_slice(frame).map_err(Error::Json),
+        | Codec::MessagePack => rmp_serde::from_slice(frame).map_err(Error::Decode),
+        }
+    }
+
+    /// Stable ID identifying this codec in the connection header.
+    pub fn id(self) -> u8 {
+        match self {
+        | Codec::Bincode => 0,
+        | Codec::Json => 1,
+        | Codec::MessagePack => 2,
+        }
+    }
+
+    /// Look up the codec identified by `id` in a connection header.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+        | 0 => Some(Codec::Bincode),
+        | 1 => Some(Codec::Json),
+        | 2 => Some(Codec::MessagePack),
+        | _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Codec {
+    type Err = String;
+    fn from_str(codec: &str) -> Result<Self, Self::Err> {
+        match codec {
+        | "bincode" => Ok(Codec::Bincode),
+        | "json" => Ok(Codec::Json),
+        | "msgpack" => Ok(Codec::MessagePack),
+        | _ => Err(format!("unknown codec {}: expected bincode, json, or msgpack", codec)),
+        }
+    }
+}
+
+impl Compression {
+    /// Flag `frame`, compressing it if it's at least `threshold` bytes long.
+    pub fn pack(&self, frame: Vec<u8>, threshold: usize) -> Result<Vec<u8>, Error> {
+        if frame.len() >= threshold {
+            let compressed = match self {
+            | Compression::Lz4 => lz4::block::compress(&frame, None, false),
+            | Compression::Zstd => zstd::block::compress(&frame, ZSTD_LEVEL),
+            }.map_err(Error::Compress)?;
+            if compressed.len() + 5 < frame.len() {
+                let mut packed = Vec::with_capacity(compressed.len() + 5);
+                packed.push(COMPRESSED);
+                packed.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+                packed.extend_from_slice(&compressed);
+                return Ok(packed)
+            }
+        }
+        let mut packed = Vec::with_capacity(frame.len() + 1);
+        packed.push(RAW);
+        packed.extend_from_slice(&frame);
+        Ok(packed)
+    }
+
+    /// Recover a frame flagged by `pack`, failing instead of decompressing
+    /// it to more than `max` bytes.
+    pub fn unpack(&self, packed: &[u8], max: usize) -> Result<Vec<u8>, Error> {
+        match packed.split_first() {
+        | None => Err(Error::Truncated),
+        | Some((&RAW, frame)) => Ok(frame.to_vec()),
+        | Some((&COMPRESSED, frame)) if frame.len() >= 4 => {
+            let mut len = [0; 4];
+            len.copy_from_slice(&frame[..4]);
+            let len = u32::from_le_bytes(len) as usize;
+            if len > max {
+                return Err(Error::Oversized(len))
+            }
+            match self {
+            | Compression::Lz4 => lz4::block::decompress(&frame[4..], Some(len as i32)),
+            | Compression::Zstd => zstd::block::decompress(&frame[4..], len),
+            }.map_err(Error::Decompress)
+        }
+        | Some((&COMPRESSED, _)) => Err(Error::Truncated),
+        | Some((flag, _)) => Err(Error::Flag(*flag)),
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+    fn from_str(compression: &str) -> Result<Self, Self::Err> {
+        match compression {
+        | "lz4" => Ok(Compression::Lz4),
+        | "zstd" => Ok(Compression::Zstd),
+        | _ => Err(format!("unknown compression {}: expected lz4 or zstd", compression)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [Compression; 2] = [Compression::Lz4, Compression::Zstd];
+
+    #[test]
+    fn packs_small_frames_raw() {
+        for compression in &ALGORITHMS {
+            let frame = vec![0u8; 63];
+            let packed = compression.pack(frame.clone(), 64).unwrap();
+            assert_eq!(packed[0], RAW);
+            assert_eq!(packed.len(), frame.len() + 1);
+            assert_eq!(compression.unpack(&packed, frame.len()).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn packs_large_frames_compressed() {
+        for compression in &ALGORITHMS {
+            let frame = vec![0u8; 64];
+            let packed = compression.pack(frame.clone(), 64).unwrap();
+            assert_eq!(packed[0], COMPRESSED);
+            assert!(packed.len() < frame.len());
+            assert_eq!(compression.unpack(&packed, frame.len()).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn packs_incompressible_frames_raw() {
+        for compression in &ALGORITHMS {
+            let frame = (0..64).map(|i| (i * 197 + 13) as u8).collect::<Vec<_>>();
+            let packed = compression.pack(frame.clone(), 16).unwrap();
+            assert_eq!(packed[0], RAW);
+            assert_eq!(compression.unpack(&packed, frame.len()).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn unpacks_frames_up_to_max() {
+        for compression in &ALGORITHMS {
+            let packed = compression.pack(vec![0u8; 64], 16).unwrap();
+            assert_eq!(compression.unpack(&packed, 64).unwrap().len(), 64);
+            match compression.unpack(&packed, 63) {
+            | Err(Error::Oversized(len)) => assert_eq!(len, 64),
+            | other => panic!("expected oversized frame, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let compression = Compression::Lz4;
+        assert!(match compression.unpack(&[], 64) { Err(Error::Truncated) => true, _ => false });
+        assert!(match compression.unpack(&[COMPRESSED, 0, 0], 64) { Err(Error::Truncated) => true, _ => false });
+        assert!(match compression.unpack(&[2, 0], 64) { Err(Error::Flag(2)) => true, _ => false });
+    }
+}
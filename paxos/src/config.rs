@@ -4,18 +4,31 @@
 //! user can create an instance of `Config` with a state implementation
 //! of their choice, and then call `run` to launch the Paxos server.
 
+use std::sync::Arc;
+
 use tokio::prelude::*;
 
+use crate::auth;
+use crate::cluster;
+use crate::codec;
+use crate::external;
+use crate::fault;
+use crate::handshake;
 use crate::internal;
+use crate::runtime;
 use crate::shared;
+use crate::span;
 use crate::state;
 use crate::thread;
+use crate::tls;
+use crate::transport;
 
 const INTERNAL_PORT: usize = 20000;
 
 /// Defines a single Paxos server with state type `S`.
-#[derive(Copy, Clone, Debug)]
-pub struct Config<S> {
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub struct Config<S: state::State> {
     /// Unique replica ID
     id: usize,
 
@@ -28,19 +41,81 @@ pub struct Config<S> {
     /// Timeout for detecting unresponsive servers
     timeout: std::time::Duration,
 
+    /// Unix domain socket for incoming client requests, if enabled
+    socket: Option<std::path::PathBuf>,
+
+    /// Port for incoming fault injection requests, if enabled
+    fault_port: Option<usize>,
+
+    /// Port for incoming metrics scrapes, if enabled
+    metrics_port: Option<usize>,
+
+    /// Port for incoming status requests, if enabled
+    admin_port: Option<usize>,
+
+    /// Directory for stable storage files
+    storage: std::path::PathBuf,
+
+    /// Whether to write structured protocol events to a trace file
+    tracing: bool,
+
+    /// Means of listening for and connecting to peers and clients
+    #[derivative(Debug = "ignore")]
+    transport: Arc<dyn transport::Transport>,
+
+    /// Certificates for encrypting peer and client connections, if enabled
+    tls: Option<tls::Tls>,
+
+    /// ID of the cluster this server belongs to, if assigned
+    cluster: Option<cluster::Cluster>,
+
+    /// Maximum size of a single frame from peers and clients (in bytes)
+    max_frame: usize,
+
+    /// Number of malformed frames tolerated per connection
+    max_malformed: usize,
+
+    /// Algorithm for compressing large frames between servers, if enabled
+    compression: Option<codec::Compression>,
+
+    /// Size from which frames between servers are compressed (in bytes)
+    compression_threshold: usize,
+
+    /// Shared secret for authenticating peer servers, if enabled
+    secret: Option<handshake::Secret>,
+
+    /// Hooks for authenticating and authorizing clients, if enabled
+    #[derivative(Debug = "ignore")]
+    auth: Option<Arc<dyn auth::Auth<S::Command>>>,
+
     _marker: std::marker::PhantomData<S>,
 }
 
 impl<S: state::State> Config<S> {
 
     /// Create a new server with unique ID `id`, out of a cluster
-    /// of `count` servers, listening on TCP port `port`.
+    /// of `count` servers, listening for clients on port `port`.
     pub fn new(id: usize, port: usize, count: usize) -> Self {
         Config {
             id,
             port,
             count,
             timeout: std::time::Duration::from_secs(1),
+            socket: None,
+            fault_port: None,
+            metrics_port: None,
+            admin_port: None,
+            storage: std::path::PathBuf::new(),
+            tracing: false,
+            transport: Arc::new(transport::Tcp),
+            tls: None,
+            cluster: None,
+            max_frame: external::MAX_FRAME,
+            max_malformed: external::MAX_MALFORMED,
+            compression: None,
+            compression_threshold: external::COMPRESSION_THRESHOLD,
+            secret: None,
+            auth: None,
             _marker: Default::default(),
         }
     }
@@ -51,104 +126,270 @@ impl<S: state::State> Config<S> {
         self
     }
 
+    /// Also listen for client requests on the Unix domain socket at `path`, replacing
+    /// any stale socket there. Launching will panic if `path` is any other kind of file.
+    /// Connections on the socket are never encrypted with TLS,
+    /// but otherwise behave like connections on the client port.
+    pub fn with_socket<P: Into<std::path::PathBuf>>(mut self, path: P) -> Self {
+        self.socket = Some(path.into());
+        self
+    }
+
+    /// Listen for `Fault`s to inject on TCP port `port`. Intended for testing only,
+    /// since any process that can connect to the port can partition this server.
+    pub fn with_fault_port(mut self, port: usize) -> Self {
+        self.fault_port = Some(port);
+        self
+    }
+
+    /// Serve metrics in the Prometheus text exposition format over HTTP on TCP port `port`.
+    pub fn with_metrics_port(mut self, port: usize) -> Self {
+        self.metrics_port = Some(port);
+        self
+    }
+
+    /// Serve this server's `Status` as JSON over HTTP on TCP port `port`.
+    pub fn with_admin_port(mut self, port: usize) -> Self {
+        self.admin_port = Some(port);
+        self
+    }
+
+    /// Keep stable storage files in directory `dir` instead of the working directory.
+    /// Servers must be restarted with the same directory to recover their state.
+    pub fn with_storage<P: Into<std::path::PathBuf>>(mut self, dir: P) -> Self {
+        self.storage = dir.into();
+        self
+    }
+
+    /// Write structured protocol events to `trace-XX.jsonl` in the storage directory.
+    pub fn with_tracing(mut self) -> Self {
+        self.tracing = true;
+        self
+    }
+
+    /// Listen for and connect to peers and clients with `transport`, which maps the client
+    /// port and internal peer ports to its own addresses. Defaults to `transport::Tcp`.
+    /// Every server in the cluster must use the same kind of transport.
+    pub fn with_transport<T: transport::Transport>(mut self, transport: T) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Encrypt all peer and client connections with `tls`. Every server in the
+    /// cluster must be configured with TLS, and clients must connect with TLS.
+    pub fn with_tls(mut self, tls: tls::Tls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Assign this server to `cluster`, which is required on first launch, and persisted
+    /// so that it can be omitted afterwards. Connections from peers and clients of other clusters
+    /// are rejected, and launching with a different cluster than before will panic.
+    pub fn with_cluster(mut self, cluster: cluster::Cluster) -> Self {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// Close connections to peers and clients that send frames longer than `bytes`,
+    /// instead of buffering them. Must be large enough for the largest command, and
+    /// for the accepted values sent to a recovering leader. Defaults to 8 MiB.
+    pub fn with_max_frame(mut self, bytes: usize) -> Self {
+        self.max_frame = bytes;
+        self
+    }
+
+    /// Close connections to peers and clients after dropping more than `count` frames
+    /// that couldn't be decoded. Defaults to 16.
+    pub fn with_max_malformed(mut self, count: usize) -> Self {
+        self.max_malformed = count;
+        self
+    }
+
+    /// Compress frames of at least `threshold` bytes sent between servers with `compression`,
+    /// such as the accepted values sent to a recovering leader. Only used on connections to
+    /// servers configured with the same algorithm.
+    pub fn with_compression(mut self, compression: codec::Compression, threshold: usize) -> Self {
+        self.compression = Some(compression);
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Require peer servers to prove knowledge of `secret` before they are trusted
+    /// with their claimed IDs. Every server in the cluster must share the same secret.
+    pub fn with_secret(mut self, secret: Vec<u8>) -> Self {
+        self.secret = Some(handshake::Secret::new(secret));
+        self
+    }
+
+    /// Require clients to log in with a credential accepted by `auth`, and consult
+    /// `auth` before proposing each of their commands.
+    pub fn with_auth<A: auth::Auth<S::Command>>(mut self, auth: A) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
     /// Launch server asynchronously.
     pub async fn run(self) {
-        let (acceptor_rx, acceptor_tx) = internal::new();
-        let (leader_rx, leader_tx) = internal::new();
-        let (_, scout_tx) = internal::new();
-        let (replica_rx, replica_tx) = internal::new();
+        // Encrypt peer and client connections if configured
+        let transport: Arc<dyn transport::Transport> = match &self.tls {
+        | Some(tls) => Arc::new(transport::Tls::new(self.transport.clone(), tls.clone())),
+        | None => self.transport.clone(),
+        };
 
         // Listen for connections to other peer servers
-        let mut internal_port = format!("127.0.0.1:{}", self.id + INTERNAL_PORT)
-            .parse::<std::net::SocketAddr>()
-            .map(|addr| tokio::net::tcp::TcpListener::bind(&addr))
-            .expect("[INTERNAL ERROR]: invalid socket address")
-            .expect("[INTERNAL ERROR]: failed to bind to socket")
-            .incoming();
+        let mut internal_port = transport.listen(self.id + INTERNAL_PORT)
+            .expect("[INTERNAL ERROR]: failed to listen for peers");
 
         // Listen for connections to clients
-        let mut external_port = format!("127.0.0.1:{}", self.port)
-            .parse::<std::net::SocketAddr>()
-            .map(|addr| tokio::net::tcp::TcpListener::bind(&addr))
-            .expect("[INTERNAL ERROR]: invalid socket address")
-            .expect("[INTERNAL ERROR]: failed to bind to socket")
-            .incoming();
-
-        // Initialize message forwarding hub
-        let shared_tx: shared::Shared<S> = shared::Shared::new(
-            self.id,
-            scout_tx,
-            replica_tx.clone(),
-            acceptor_tx.clone(),
-        );
-
-        let acceptor_thread = thread::acceptor::Acceptor::new(
-            self.id,
-            acceptor_rx,
-            shared_tx.clone(),
-        );
+        let mut external_port = transport.listen(self.port)
+            .expect("[INTERNAL ERROR]: failed to listen for clients");
 
-        let replica_thread = thread::replica::Replica::new(
-            self.id,
-            leader_tx.clone(),
-            shared_tx.clone(),
-            replica_rx,
-        );
-
-        let leader_thread = thread::leader::Leader::new(
+        // Spawn persistent acceptor, replica, and leader threads
+        let cluster = cluster::resolve(self.id, &self.storage, self.cluster);
+        let (shared_tx, acceptor_tx, replica_tx) = spawn::<S>(
             self.id,
             self.count,
-            leader_rx,
-            leader_tx.clone(),
-            shared_tx.clone(),
             self.timeout,
+            Box::new(runtime::Tcp::default()),
+            Arc::new(runtime::Tokio),
+            Arc::new(runtime::Thread),
+            &self.storage,
+            cluster,
+            if self.tracing { Some(Arc::new(span::Tracer::new(self.id, &self.storage))) } else { None },
         );
 
+        // Frame messages with bincode, unless a client negotiates another codec
+        let framing = external::Framing::default()
+            .with_max_frame(self.max_frame)
+            .with_max_malformed(self.max_malformed);
+        let compression = self.compression;
+        let threshold = self.compression_threshold;
+
         // Asynchronously listen for and create new server-to-server connections
         let acceptor = acceptor_tx.clone();
         let self_id = self.id;
         let timeout = self.timeout;
         let shared = shared_tx.clone();
+        let secret = self.secret.clone();
         tokio::spawn_async(async move {
-            while let Some(Ok(stream)) = await!(internal_port.next()) {
-                let connecting = thread::peer::Connecting::new(
-                    self_id,
-                    stream,
-                    acceptor.clone(),
-                    shared.clone(),
-                    timeout,
-                );
-                tokio::spawn(connecting.and_then(|peer| peer));
+            while let Some(Ok(connecting)) = await!(internal_port.next()) {
+                let acceptor = acceptor.clone();
+                let shared = shared.clone();
+                let secret = secret.clone();
+                let connecting = connecting
+                    .map_err(|_| ())
+                    .and_then(move |stream| handshake::peer::<S>(stream, cluster, self_id, None, secret, compression, timeout))
+                    .and_then(move |(peer_id, compression, stream)| {
+                        thread::peer::Peer::new(
+                            self_id,
+                            peer_id,
+                            stream,
+                            framing.with_compression(compression, threshold),
+                            acceptor,
+                            shared,
+                            timeout,
+                        )
+                    });
+                tokio::spawn(connecting);
             }
         });
 
         // Asynchronously listen for and create new server-to-client connections
+        let replica = replica_tx.clone();
         let shared = shared_tx.clone();
+        let auth = self.auth.clone();
         tokio::spawn_async(async move {
-            while let Some(Ok(stream)) = await!(external_port.next()) {
-                let connecting = thread::client::Connecting::new(
-                    stream,
-                    replica_tx.clone(),
+            while let Some(Ok(connecting)) = await!(external_port.next()) {
+                tokio::spawn(accept_client(
+                    connecting,
+                    cluster,
+                    framing,
+                    auth.clone(),
+                    replica.clone(),
                     shared.clone(),
-                );
-                tokio::spawn(connecting.and_then(|client| client));
+                    timeout,
+                ));
             }
         });
 
+        // Asynchronously listen for and create new server-to-client connections
+        // from processes on the same host, which don't need to be encrypted
+        if let Some(path) = &self.socket {
+            let mut socket = transport::Unix::bind(path)
+                .expect("[INTERNAL ERROR]: failed to bind to Unix socket");
+            let replica = replica_tx.clone();
+            let shared = shared_tx.clone();
+            let auth = self.auth.clone();
+            tokio::spawn_async(async move {
+                while let Some(Ok(connecting)) = await!(socket.next()) {
+                    tokio::spawn(accept_client(
+                        connecting,
+                        cluster,
+                        framing,
+                        auth.clone(),
+                        replica.clone(),
+                        shared.clone(),
+                        timeout,
+                    ));
+                }
+            });
+        }
+
+        // Asynchronously listen for and apply injected faults
+        if let Some(fault_port) = self.fault_port {
+            let mut fault_port = format!("127.0.0.1:{}", fault_port)
+                .parse::<std::net::SocketAddr>()
+                .map(|addr| tokio::net::tcp::TcpListener::bind(&addr))
+                .expect("[INTERNAL ERROR]: invalid socket address")
+                .expect("[INTERNAL ERROR]: failed to bind to socket")
+                .incoming();
+            let shared = shared_tx.clone();
+            tokio::spawn_async(async move {
+                while let Some(Ok(stream)) = await!(fault_port.next()) {
+                    let (rx, _) = external::new::<fault::Fault, (), _>(stream);
+                    let shared = shared.clone();
+                    let injecting = rx
+                        .for_each(move |fault| {
+                            shared.write().inject(fault);
+                            Ok(())
+                        })
+                        .map_err(|error| warn!("closing fault connection: {}", error));
+                    tokio::spawn(injecting);
+                }
+            });
+        }
+
+        // Asynchronously listen for and respond to metrics scrapes
+        if let Some(metrics_port) = self.metrics_port {
+            let shared = shared_tx.clone();
+            serve(metrics_port, "text/plain; version=0.0.4", move || {
+                shared.read().metrics().export()
+            });
+        }
+
+        // Asynchronously listen for and respond to status requests
+        if let Some(admin_port) = self.admin_port {
+            let shared = shared_tx.clone();
+            serve(admin_port, "application/json", move || {
+                serde_json::to_string(&shared.read().status())
+                    .expect("[INTERNAL ERROR]: failed to serialize status")
+            });
+        }
+
         // Attempt to connect to all other servers directly on startup
         for peer_id in (0..self.count).filter(|id| *id != self_id) {
             let acceptor = acceptor_tx.clone();
             let shared = shared_tx.clone();
-            let addr = format!("127.0.0.1:{}", peer_id + INTERNAL_PORT)
-                .parse::<std::net::SocketAddr>()
-                .unwrap();
-            let connect = tokio::net::tcp::TcpStream::connect(&addr)
+            let secret = self.secret.clone();
+            let connect = transport.connect(peer_id + INTERNAL_PORT)
                 .map_err(|_| ())
-                .and_then(move |stream| {
+                .and_then(move |stream| handshake::peer::<S>(stream, cluster, self_id, Some(peer_id), secret, compression, timeout))
+                .and_then(move |(_, compression, stream)| {
                     thread::peer::Peer::new(
                         self_id,
                         peer_id,
                         stream,
+                        framing.with_compression(compression, threshold),
                         acceptor.clone(),
                         shared.clone(),
                         timeout,
@@ -156,10 +397,140 @@ impl<S: state::State> Config<S> {
                 });
             tokio::spawn(connect);
         }
+    }
+}
 
-        // Spawn persistent acceptor, replica, and leader threads
-        tokio::spawn(acceptor_thread);
-        tokio::spawn(replica_thread);
-        tokio::spawn(leader_thread);
+/// Negotiates with the client on the other end of `connecting` once it's connected,
+/// then forwards its requests to the replica until it disconnects.
+fn accept_client<S: state::State>(
+    connecting: transport::Pending,
+    cluster: cluster::Cluster,
+    framing: external::Framing,
+    auth: Option<Arc<dyn auth::Auth<S::Command>>>,
+    replica_tx: internal::Tx<thread::replica::In<S::Command>>,
+    shared_tx: shared::Shared<S>,
+    timeout: std::time::Duration,
+) -> impl Future<Item = (), Error = ()> {
+    let login = auth.clone();
+    connecting
+        .map_err(|_| ())
+        .and_then(move |stream| handshake::client::<S>(stream, cluster, login, timeout))
+        .and_then(move |(identity, codec, stream)| {
+            thread::client::Connecting::new(
+                stream,
+                framing.with_codec(codec),
+                replica_tx,
+                shared_tx,
+                identity,
+                auth,
+            )
+        })
+        .and_then(|client| client)
+}
+
+/// Listens for HTTP requests on TCP port `port`, and responds to each
+/// one with the output of `render`, regardless of the requested path.
+fn serve<F>(port: usize, content_type: &'static str, render: F)
+    where F: Fn() -> String + Send + Sync + 'static
+{
+    let mut listener = format!("127.0.0.1:{}", port)
+        .parse::<std::net::SocketAddr>()
+        .map(|addr| tokio::net::tcp::TcpListener::bind(&addr))
+        .expect("[INTERNAL ERROR]: invalid socket address")
+        .expect("[INTERNAL ERROR]: failed to bind to socket")
+        .incoming();
+    let render = Arc::new(render);
+    tokio::spawn_async(async move {
+        while let Some(Ok(stream)) = await!(listener.next()) {
+            let render = render.clone();
+            let respond = tokio::io::read(stream, vec![0; 1024])
+                .and_then(move |(stream, _, _)| {
+                    let body = render();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        content_type,
+                        body.len(),
+                        body,
+                    );
+                    tokio::io::write_all(stream, response)
+                })
+                .map(|_| ())
+                .map_err(|_| ());
+            tokio::spawn(respond);
+        }
+    });
+}
+
+/// Spawns persistent acceptor, replica, and leader threads for server `id` of `cluster`
+/// in the provided environment, keeping stable storage files in directory `storage`
+/// and writing protocol events to `tracer` if provided. Returns the
+/// shared transmission hub, along with the acceptor and replica channels that peer and
+/// client connections forward messages to.
+///
+/// Must be called from within an executor, since the leader immediately spawns a scout.
+pub(crate) fn spawn<S: state::State>(
+    id: usize,
+    count: usize,
+    timeout: std::time::Duration,
+    network: Box<dyn runtime::Network<S::Command>>,
+    clock: Arc<dyn runtime::Clock>,
+    random: Arc<dyn runtime::Random>,
+    storage: &std::path::Path,
+    cluster: cluster::Cluster,
+    tracer: Option<Arc<span::Tracer>>,
+) -> (
+    shared::Shared<S>,
+    internal::Tx<thread::acceptor::In<S::Command>>,
+    internal::Tx<thread::replica::In<S::Command>>,
+) {
+    let (acceptor_rx, acceptor_tx) = internal::new();
+    let (leader_rx, leader_tx) = internal::new();
+    let (_, scout_tx) = internal::new();
+    let (replica_rx, replica_tx) = internal::new();
+
+    // Initialize message forwarding hub
+    let shared_tx: shared::Shared<S> = shared::Shared::new(
+        id,
+        network,
+        clock,
+        random,
+        scout_tx,
+        replica_tx.clone(),
+        acceptor_tx.clone(),
+    );
+
+    shared_tx.write().join(cluster);
+    shared_tx.write().store_in(storage.to_path_buf());
+
+    if let Some(tracer) = tracer {
+        shared_tx.write().trace_to(tracer);
     }
+
+    let acceptor_thread = thread::acceptor::Acceptor::new(
+        id,
+        acceptor_rx,
+        shared_tx.clone(),
+    );
+
+    let replica_thread = thread::replica::Replica::new(
+        id,
+        leader_tx.clone(),
+        shared_tx.clone(),
+        replica_rx,
+    );
+
+    let leader_thread = thread::leader::Leader::new(
+        id,
+        count,
+        leader_rx,
+        leader_tx.clone(),
+        shared_tx.clone(),
+        timeout,
+    );
+
+    tokio::spawn(acceptor_thread);
+    tokio::spawn(replica_thread);
+    tokio::spawn(leader_thread);
+
+    (shared_tx, acceptor_tx, replica_tx)
 }
@@ -1,84 +1,407 @@
 //! # Summary
 //!
 //! This module abstracts over external connections to clients and peer servers.
-//! 
-//! Currently uses `tokio-serde-bincode` to wrap around `tokio`'s length-delimited
-//! codec, which in turn wraps around `tokio`'s asynchronous TCP stream. This allows
-//! us to serialize and deserialize Rust structs through a TCP connection with
-//! minimal boilerplate on the sending and receiving ends.
+//!
+//! Currently wraps `tokio`'s length-delimited codec around any asynchronous byte
+//! stream: usually a TCP stream, optionally encrypted with TLS. Each frame holds
+//! a single message, encoded with the connection's `Codec`. This allows us to
+//! serialize and deserialize Rust structs through a connection with minimal
+//! boilerplate on the sending and receiving ends.
+//!
+//! Frames longer than the connection's maximum size end the connection before
+//! they are buffered, so that a misbehaving client can't exhaust our memory with
+//! a huge length prefix. Outgoing messages that would encode to a longer frame,
+//! before or after compression, fail to send instead of being rejected by the
+//! other end. Since frame boundaries survive a frame that fails to decode,
+//! malformed frames are dropped and counted instead, and only end the
+//! connection once there are more than the connection tolerates.
+//!
+//! Connections between servers can also negotiate a `Compression` algorithm
+//! during the handshake, which compresses frames above a size threshold.
+
+use std::sync::Arc;
 
-use futures::{sink, stream};
 use tokio::prelude::*;
 use tokio::codec::{FramedRead, FramedWrite, LengthDelimitedCodec, length_delimited};
-use tokio_serde_bincode::{ReadBincode, WriteBincode};
-
-/// External receiving channel. Expects length-delimited, bincode-encoded
-/// Rust data of type `T` sent via TCP.
-pub struct Rx<T>(
-    ReadBincode<
-        stream::FromErr<
-            FramedRead<
-                tokio::io::ReadHalf<tokio::net::TcpStream>,
-                LengthDelimitedCodec,
-            >,
-            bincode::Error,
-        >,
-        T
-    >
-);
-
-/// External transmission channel. Sends length-delimited, bincode-encoded
-/// Rust data of type `T` over TCP.
-pub struct Tx<T>(
-    WriteBincode<
-        sink::SinkFromErr<
-            FramedWrite<
-                tokio::io::WriteHalf<tokio::net::TcpStream>,
-                LengthDelimitedCodec,
-            >,
-            bincode::Error,
-        >,
-        T,
-    >
-);
-
-/// Split a `tokio::net::TcpStream` into a pair of receiving and transmitting
-/// channels capable of reading and writing bincode-encoded data.
-pub fn new<R, T>(stream: tokio::net::TcpStream) -> (Rx<R>, Tx<T>)
+
+use crate::codec;
+use crate::codec::{Codec, Compression};
+use crate::metrics;
+
+/// Default maximum size of a single frame (in bytes)
+pub const MAX_FRAME: usize = 8 * 1024 * 1024;
+
+/// Default number of malformed frames tolerated per connection
+pub const MAX_MALFORMED: usize = 16;
+
+/// Default size from which frames are compressed (in bytes)
+pub const COMPRESSION_THRESHOLD: usize = 4096;
+
+/// Asynchronous byte stream that external channels can be created from.
+pub trait Io: AsyncRead + AsyncWrite + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + 'static> Io for T {}
+
+/// Describes how messages are framed on a connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Framing {
+    /// Encoding of each frame
+    codec: Codec,
+
+    /// Maximum size of a single frame (in bytes)
+    max_frame: usize,
+
+    /// Number of malformed frames to drop before closing the connection
+    max_malformed: usize,
+
+    /// Algorithm for compressing frames, if negotiated
+    compression: Option<Compression>,
+
+    /// Size from which frames are compressed (in bytes)
+    threshold: usize,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Framing {
+            codec: Codec::default(),
+            max_frame: MAX_FRAME,
+            max_malformed: MAX_MALFORMED,
+            compression: None,
+            threshold: COMPRESSION_THRESHOLD,
+        }
+    }
+}
+
+impl Framing {
+    /// Encode frames with `codec`.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Close the connection instead of reading or writing frames longer than `bytes`.
+    pub fn with_max_frame(mut self, bytes: usize) -> Self {
+        self.max_frame = bytes;
+        self
+    }
+
+    /// Close the connection after dropping more than `count` malformed frames.
+    pub fn with_max_malformed(mut self, count: usize) -> Self {
+        self.max_malformed = count;
+        self
+    }
+
+    /// Compress frames of at least `threshold` bytes with `compression`, if any.
+    /// Both ends of the connection must agree on the algorithm.
+    pub fn with_compression(mut self, compression: Option<Compression>, threshold: usize) -> Self {
+        self.compression = compression;
+        self.threshold = threshold;
+        self
+    }
+
+    /// Encode `message` into a single frame, failing if either the frame sent or
+    /// the frame before compression would be longer than the maximum size, since
+    /// the other end would reject it when reading or decompressing it.
+    fn encode<T: serde::Serialize>(&self, message: &T) -> Result<Vec<u8>, codec::Error> {
+        let frame = self.codec.encode(message)?;
+        let len = frame.len();
+        let packed = match &self.compression {
+        | None => frame,
+        | Some(compression) => compression.pack(frame, self.threshold)?,
+        };
+        if len.max(packed.len()) > self.max_frame {
+            return Err(codec::Error::Oversized(len.max(packed.len())))
+        }
+        Ok(packed)
+    }
+
+    /// Decode a single frame.
+    fn decode<T: serde::de::DeserializeOwned>(&self, frame: &[u8]) -> Result<T, codec::Error> {
+        match &self.compression {
+        | None => self.codec.decode(frame),
+        | Some(compression) => self.codec.decode(&compression.unpack(frame, self.max_frame)?),
+        }
+    }
+
+    /// Returns the encoding of each frame.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    fn builder(&self) -> length_delimited::Builder {
+        let mut builder = length_delimited::Builder::new();
+        builder.max_frame_length(self.max_frame);
+        builder
+    }
+}
+
+/// Reason for closing an external channel.
+#[derive(Debug)]
+pub enum Error {
+    /// Stream was closed or failed, or a frame exceeded the maximum size
+    Io(std::io::Error),
+
+    /// Outgoing message could not be encoded
+    Encode(codec::Error),
+
+    /// Too many incoming frames could not be decoded, the last with the attached error
+    Malformed(usize, codec::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+        | Error::Io(error) => write!(fmt, "connection failed: {}", error),
+        | Error::Encode(error) => write!(fmt, "could not encode message: {}", error),
+        | Error::Malformed(count, error) => write!(fmt, "received {} malformed frames, last: {}", count, error),
+        }
+    }
+}
+
+/// External receiving channel. Expects length-delimited Rust data of type `T`,
+/// encoded with the connection's codec.
+pub struct Rx<T> {
+    frames: FramedRead<tokio::io::ReadHalf<Box<dyn Io>>, LengthDelimitedCodec>,
+    framing: Framing,
+    malformed: usize,
+    metrics: Option<Arc<metrics::Metrics>>,
+    _marker: std::marker::PhantomData<T>,
+}
+
+/// External transmission channel. Sends length-delimited Rust data of type `T`,
+/// encoded with the connection's codec.
+pub struct Tx<T> {
+    frames: FramedWrite<tokio::io::WriteHalf<Box<dyn Io>>, LengthDelimitedCodec>,
+    framing: Framing,
+    _marker: std::marker::PhantomData<T>,
+}
+
+/// Split a stream (e.g. `tokio::net::TcpStream`) into a pair of receiving and
+/// transmitting channels capable of reading and writing bincode-encoded data.
+pub fn new<R, T, S>(stream: S) -> (Rx<R>, Tx<T>)
+where R: serde::de::DeserializeOwned,
+      T: serde::Serialize,
+      S: Io,
+{
+    with_framing(stream, Framing::default())
+}
+
+/// Split a stream into a pair of receiving and transmitting channels capable
+/// of reading and writing data encoded with `codec`.
+pub fn with_codec<R, T, S>(stream: S, codec: Codec) -> (Rx<R>, Tx<T>)
 where R: serde::de::DeserializeOwned,
       T: serde::Serialize,
+      S: Io,
 {
+    with_framing(stream, Framing::default().with_codec(codec))
+}
+
+/// Split a stream into a pair of receiving and transmitting channels capable
+/// of reading and writing data framed according to `framing`.
+pub fn with_framing<R, T, S>(stream: S, framing: Framing) -> (Rx<R>, Tx<T>)
+where R: serde::de::DeserializeOwned,
+      T: serde::Serialize,
+      S: Io,
+{
+    let stream: Box<dyn Io> = Box::new(stream);
     let (rx, tx) = stream.split();
-    let rx = length_delimited::Builder::new()
-        .new_read(rx)
-        .from_err::<bincode::Error>();
-    let tx = length_delimited::Builder::new()
-        .new_write(tx)
-        .sink_from_err::<bincode::Error>();
-    (Rx(ReadBincode::new(rx)), Tx(WriteBincode::new(tx)))
+    let rx = Rx {
+        frames: framing.builder().new_read(rx),
+        framing,
+        malformed: 0,
+        metrics: None,
+        _marker: Default::default(),
+    };
+    let tx = Tx {
+        frames: framing.builder().new_write(tx),
+        framing,
+        _marker: Default::default(),
+    };
+    (rx, tx)
+}
+
+impl<R> Rx<R> {
+    /// Counts every malformed frame in `metrics`.
+    pub(crate) fn with_metrics(mut self, metrics: Arc<metrics::Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Returns the number of malformed frames dropped so far.
+    pub fn malformed(&self) -> usize {
+        self.malformed
+    }
 }
 
 impl<R: serde::de::DeserializeOwned> Stream for Rx<R> {
     type Item = R;
-    type Error = ();
+    type Error = Error;
 
     #[inline]
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
-        self.0.poll().map_err(|_| ())
+        loop {
+            let frame = match self.frames.poll().map_err(Error::Io)? {
+            | Async::Ready(Some(frame)) => frame,
+            | Async::Ready(None) => return Ok(Async::Ready(None)),
+            | Async::NotReady => return Ok(Async::NotReady),
+            };
+            match self.framing.decode(&frame) {
+            | Ok(message) => return Ok(Async::Ready(Some(message))),
+            | Err(error) => {
+                self.malformed += 1;
+                if let Some(metrics) = &self.metrics {
+                    metrics.malformed_frames.inc();
+                }
+                if self.malformed > self.framing.max_malformed {
+                    return Err(Error::Malformed(self.malformed, error))
+                }
+                warn!("dropped malformed frame: {}", error);
+            }
+            }
+        }
     }
 }
 
 impl<T: serde::Serialize> Sink for Tx<T> {
-    type SinkItem = T; 
-    type SinkError = ();
+    type SinkItem = T;
+    type SinkError = Error;
 
     #[inline]
     fn start_send(&mut self, item: Self::SinkItem) -> Result<AsyncSink<Self::SinkItem>, Self::SinkError> {
-        self.0.start_send(item).map_err(|_| ())
+        let frame = self.framing.encode(&item).map_err(Error::Encode)?;
+        match self.frames.start_send(frame.into()).map_err(Error::Io)? {
+        | AsyncSink::Ready => Ok(AsyncSink::Ready),
+        | AsyncSink::NotReady(_) => Ok(AsyncSink::NotReady(item)),
+        }
     }
 
     #[inline]
     fn poll_complete(&mut self) -> Result<Async<()>, Self::SinkError> {
-        self.0.poll_complete().map_err(|_| ())
+        self.frames.poll_complete().map_err(Error::Io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Maximum frame size used by the tests, small enough to hit exactly.
+    const MAX: usize = 64;
+
+    /// Bincode-encoded length of a `Vec<u8>` with `len` elements.
+    fn encoded(len: usize) -> usize {
+        len + 8
+    }
+
+    /// Bytes that neither compression algorithm can shrink at these sizes.
+    fn incompressible(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 197 + 13) as u8).collect()
+    }
+
+    /// Length-prefixed frame holding `bytes`, as written by `LengthDelimitedCodec`.
+    fn frame(bytes: &[u8]) -> Vec<u8> {
+        let mut frame = (bytes.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(bytes);
+        frame
+    }
+
+    /// Write `bytes` to a connection framed by `framing`, close it, and collect
+    /// every message received before it ended.
+    fn receive(framing: Framing, bytes: Vec<u8>) -> Result<Vec<Vec<u8>>, Error> {
+        let (mut a, b) = crate::transport::Pipe::pair();
+        std::io::Write::write_all(&mut a, &bytes).unwrap();
+        std::mem::drop(a);
+        let (rx, _) = with_framing::<Vec<u8>, (), _>(b, framing);
+        tokio::runtime::Runtime::new().unwrap().block_on(rx.collect())
+    }
+
+    #[test]
+    fn closes_connection_on_oversized_frame() {
+        let framing = Framing::default().with_max_frame(MAX);
+        let mut bytes = frame(&bincode::serialize(&vec![0u8; MAX - 8]).unwrap());
+        bytes.extend(frame(&vec![0u8; MAX + 1]));
+        match receive(framing, bytes) {
+        | Err(Error::Io(_)) => (),
+        | other => panic!("expected oversized frame to close connection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn drops_malformed_frames_up_to_threshold() {
+        let framing = Framing::default().with_max_malformed(2);
+        let valid = vec![1u8, 2, 3];
+        let mut bytes = Vec::new();
+        for _ in 0..2 {
+            bytes.extend(frame(&[0xff]));
+        }
+        bytes.extend(frame(&bincode::serialize(&valid).unwrap()));
+        assert_eq!(receive(framing, bytes).unwrap(), vec![valid]);
+    }
+
+    #[test]
+    fn closes_connection_after_threshold() {
+        let framing = Framing::default().with_max_malformed(2);
+        let mut bytes = Vec::new();
+        for _ in 0..3 {
+            bytes.extend(frame(&[0xff]));
+        }
+        bytes.extend(frame(&bincode::serialize(&vec![1u8, 2, 3]).unwrap()));
+        match receive(framing, bytes) {
+        | Err(Error::Malformed(3, _)) => (),
+        | other => panic!("expected third malformed frame to close connection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encodes_frames_up_to_max() {
+        let framing = Framing::default().with_max_frame(MAX);
+        assert_eq!(framing.encode(&vec![0u8; MAX - 8]).unwrap().len(), encoded(MAX - 8));
+        match framing.encode(&vec![0u8; MAX - 7]) {
+        | Err(codec::Error::Oversized(len)) => assert_eq!(len, MAX + 1),
+        | other => panic!("expected oversized frame, got {:?}", other.map(|frame| frame.len())),
+        }
+    }
+
+    #[test]
+    fn counts_compression_flag_below_threshold() {
+        let framing = Framing::default()
+            .with_max_frame(MAX)
+            .with_compression(Some(Compression::Lz4), MAX * 2);
+        assert_eq!(framing.encode(&vec![0u8; MAX - 9]).unwrap().len(), MAX);
+        assert!(framing.encode(&vec![0u8; MAX - 8]).is_err());
+    }
+
+    #[test]
+    fn counts_compression_flag_above_threshold() {
+        for compression in &[Compression::Lz4, Compression::Zstd] {
+            let framing = Framing::default()
+                .with_max_frame(MAX)
+                .with_compression(Some(*compression), 16);
+
+            // Compressible frames shrink, but must still fit once decompressed
+            assert!(framing.encode(&vec![0u8; MAX - 8]).unwrap().len() < MAX);
+            assert!(framing.encode(&vec![0u8; MAX - 7]).is_err());
+
+            // Incompressible frames are sent as is, after the flag byte
+            assert_eq!(framing.encode(&incompressible(MAX - 9)).unwrap().len(), MAX);
+            assert!(framing.encode(&incompressible(MAX - 8)).is_err());
+        }
+    }
+
+    #[test]
+    fn sends_frames_of_max_size() {
+        let framing = Framing::default()
+            .with_max_frame(MAX)
+            .with_compression(Some(Compression::Lz4), 16);
+        let (a, b) = crate::transport::Pipe::pair();
+        let (_, tx) = with_framing::<(), Vec<u8>, _>(a, framing);
+        let (rx, _) = with_framing::<Vec<u8>, (), _>(b, framing);
+        let message = incompressible(MAX - 9);
+        let exchange = tx.send(message.clone())
+            .and_then(|_| rx.into_future().map_err(|(error, _)| error))
+            .map(|(received, _)| received);
+        let received = tokio::runtime::Runtime::new().unwrap().block_on(exchange).unwrap();
+        assert_eq!(received, Some(message));
     }
 }
@@ -0,0 +1,43 @@
+//! # Summary
+//!
+//! This module defines link-level faults that can be injected into a running
+//! server for testing. Faults only affect messages sent from this server to its
+//! peers, so asymmetric partitions can be created by injecting faults into
+//! only one side of a connection.
+//!
+//! Faults are delivered over a separate TCP listener, enabled with
+//! `Config::with_fault_port`, as length-delimited bincode-encoded `Fault`s.
+
+use std::time;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Fault to inject into outgoing connections to peer servers.
+#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// Drop all messages to the provided peer
+    Block(usize),
+
+    /// Delay all messages to the provided peer by a fixed duration
+    Delay(usize, time::Duration),
+
+    /// Drop messages to the provided peer with the given probability
+    Drop(usize, f64),
+
+    /// Remove all faults
+    Heal,
+}
+
+/// Current faults on the outgoing connection to a single peer.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Link {
+    /// Drop all messages
+    pub blocked: bool,
+
+    /// Delay before sending each message
+    pub delay: time::Duration,
+
+    /// Probability of dropping each message
+    pub drop_rate: f64,
+}
@@ -0,0 +1,641 @@
+//! # Summary
+//!
+//! This module negotiates peer and client connections before any protocol
+//! messages are exchanged. The end that opened the connection first sends a
+//! fixed five-byte header: the wire protocol version as a big-endian `u32`,
+//! followed by the ID of the `Codec` it will encode messages with. The other
+//! end replies with its own header, echoing the codec if it supports it. Since
+//! the header never depends on the codec, clients can negotiate a connection
+//! without implementing bincode.
+//!
+//! Every later frame on the connection, including the rest of the handshake, is
+//! encoded with the negotiated codec. Both ends then exchange a `Preamble` with
+//! their cluster ID and state machine tag, and close the connection with a logged
+//! error if the versions, codecs, or preambles differ, rather than failing to
+//! deserialize messages later on.
+//!
+//! Both ends of a peer connection, which always use bincode, then run an
+//! authentication handshake:
+//!
+//! 1. Both send a `Hello` with their server ID, a random nonce, and the compression
+//!    algorithm they're configured with, and receive the other server's `Hello`
+//! 2. The initiator, which dialed the connection, sends a `Proof`: an HMAC-SHA256
+//!    over its `Role` and the `Transcript` of the handshake so far, keyed by the
+//!    shared cluster secret
+//! 3. The responder verifies the initiator's `Proof` before sending its own
+//! 4. The initiator verifies the responder's `Proof`
+//!
+//! A server that doesn't know the cluster secret can't produce a valid proof
+//! for any ID, so it can't impersonate another server. Since every proof covers
+//! both `Hello`s, the preamble, and the role of the server that signed it,
+//! a proof can't be replayed into another handshake, relayed from another connection,
+//! or reflected back at the server that sent the challenge. And since responders
+//! only sign after verifying the initiator, they can't be used as signing oracles.
+//! Without a configured secret, proofs are empty and claimed IDs are trusted.
+//! Frames on the connection are compressed only if both servers are configured
+//! with the same algorithm, and since proofs cover the advertised algorithms, a
+//! relay can't strip or swap them without failing authentication.
+//!
+//! Clients instead send a `Login` with their credential, which is only checked
+//! if the server is configured with `Config::with_auth`, and wait for the server's
+//! `Verdict`. Servers accept clients using any supported codec.
+//!
+//! Handshake frames are written directly to the underlying stream, prefixed by
+//! their length as a big-endian `u32` like every later frame, so that no bytes
+//! are buffered past the end of the handshake.
+
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac};
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::prelude::*;
+
+use crate::auth;
+use crate::cluster;
+use crate::codec;
+use crate::codec::{Codec, Compression};
+use crate::external;
+use crate::state;
+
+/// Version of the wire protocol. Must be incremented whenever the handshake
+/// frames, `message`, or `thread::peer::In` change incompatibly.
+pub const PROTOCOL_VERSION: u32 = 6;
+
+/// Maximum size of a single handshake frame (in bytes)
+const MAX_FRAME: usize = 1024;
+
+/// Length of the header that begins every connection (in bytes)
+const HEADER: usize = 5;
+
+/// Byte stream being negotiated.
+type Connection = Box<dyn external::Io>;
+
+/// Shared secret for authenticating peer servers.
+#[derive(Clone)]
+pub(crate) struct Secret(Arc<Vec<u8>>);
+
+impl Secret {
+    pub fn new(secret: Vec<u8>) -> Self {
+        Secret(Arc::new(secret))
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "Secret(..)")
+    }
+}
+
+/// First frame sent by both ends of every connection after the header.
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+struct Preamble {
+    /// ID of the cluster the sender belongs to or is connecting to
+    cluster: cluster::Cluster,
+
+    /// Tag of the sender's state machine
+    machine: String,
+}
+
+impl Preamble {
+    fn new<S: state::State>(cluster: cluster::Cluster) -> Self {
+        Preamble {
+            cluster,
+            machine: S::tag().to_string(),
+        }
+    }
+}
+
+/// Identifies the sending server and challenges the receiving server.
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+struct Hello {
+    /// Claimed ID of the sending server
+    id: usize,
+
+    /// Random challenge for the receiving server
+    nonce: [u8; 32],
+
+    /// Compression algorithm the sending server is configured with
+    compression: Option<Compression>,
+}
+
+/// Side of a peer connection.
+#[derive(Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Role {
+    /// Server that dialed the connection
+    Initiator,
+
+    /// Server that accepted the connection
+    Responder,
+}
+
+/// Everything exchanged before the proofs of a peer handshake, as signed by both servers.
+#[derive(Serialize)]
+#[derive(Clone, Debug)]
+struct Transcript {
+    /// Wire protocol version
+    version: u32,
+
+    /// Preamble shared by both servers
+    preamble: Preamble,
+
+    /// ID, nonce, and compression algorithm of the initiator
+    initiator: Hello,
+
+    /// ID, nonce, and compression algorithm of the responder
+    responder: Hello,
+}
+
+impl Transcript {
+    fn new(preamble: Preamble, initiator: &Hello, responder: &Hello) -> Self {
+        Transcript {
+            version: PROTOCOL_VERSION,
+            preamble,
+            initiator: initiator.clone(),
+            responder: responder.clone(),
+        }
+    }
+}
+
+/// Response to the other server's challenge.
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+struct Proof {
+    /// HMAC over the sender's role and the transcript
+    mac: Vec<u8>,
+}
+
+/// Credential presented by a connecting client.
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+struct Login {
+    credential: Vec<u8>,
+}
+
+/// Server's response to a client's `Login`.
+#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug)]
+enum Verdict {
+    Accepted,
+    Rejected,
+}
+
+/// Reason for rejecting a connection.
+#[derive(Debug)]
+pub enum Error {
+    /// Stream was closed or failed
+    Io(std::io::Error),
+
+    /// Frame could not be decoded
+    Decode(codec::Error),
+
+    /// Frame exceeded `MAX_FRAME`
+    Oversized(usize),
+
+    /// Other end speaks a different protocol version
+    Version { ours: u32, theirs: u32 },
+
+    /// Codec with the attached ID is not supported by the other end, or by us
+    Codec(u8),
+
+    /// Other end belongs to a different cluster
+    Cluster { ours: cluster::Cluster, theirs: cluster::Cluster },
+
+    /// Other end runs a different state machine
+    Machine { ours: String, theirs: String },
+
+    /// Peer claimed our own ID, or not the ID we connected to
+    Identity(usize),
+
+    /// Peer could not prove its claimed ID
+    Unauthenticated(usize),
+
+    /// Client presented an invalid credential
+    Credential,
+
+    /// Server rejected our credential
+    Rejected,
+
+    /// Peer did not complete the handshake in time
+    Timeout,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+        | Error::Io(error) => write!(fmt, "connection failed: {}", error),
+        | Error::Decode(error) => write!(fmt, "invalid handshake frame: {}", error),
+        | Error::Oversized(len) => write!(fmt, "handshake frame of {} bytes is too large", len),
+        | Error::Version { ours, theirs } => write!(fmt, "protocol version {} is incompatible with ours ({})", theirs, ours),
+        | Error::Codec(id) => write!(fmt, "codec with ID {} is not supported", id),
+        | Error::Cluster { ours, theirs } => write!(fmt, "cluster {} does not match ours ({})", theirs, ours),
+        | Error::Machine { ours, theirs } => write!(fmt, "state machine {} does not match ours ({})", theirs, ours),
+        | Error::Identity(id) => write!(fmt, "unexpected server ID {}", id),
+        | Error::Unauthenticated(id) => write!(fmt, "invalid proof for server ID {}", id),
+        | Error::Credential => write!(fmt, "invalid client credential"),
+        | Error::Rejected => write!(fmt, "credential rejected by server"),
+        | Error::Timeout => write!(fmt, "handshake timed out"),
+        }
+    }
+}
+
+/// Authenticate the peer server on the other end of `stream`, returning its ID and
+/// the negotiated compression algorithm, if both of us are configured with `compression`.
+/// If we initiated the connection, `expected` is the ID of the server we dialed.
+pub(crate) fn peer<S: state::State>(
+    stream: Connection,
+    cluster: cluster::Cluster,
+    self_id: usize,
+    expected: Option<usize>,
+    secret: Option<Secret>,
+    compression: Option<Compression>,
+    timeout: std::time::Duration,
+) -> impl Future<Item = (usize, Option<Compression>, Connection), Error = ()> {
+    let preamble = Preamble::new::<S>(cluster);
+    let ours = Hello { id: self_id, nonce: rand::random(), compression };
+    let role = if expected.is_some() { Role::Initiator } else { Role::Responder };
+    let codec = Codec::Bincode;
+    let greeted = match role {
+    | Role::Initiator => future::Either::A(initiate(stream, codec, preamble.clone())),
+    | Role::Responder => future::Either::B(
+        answer(stream, preamble.clone()).and_then(move |(stream, theirs)| {
+            if theirs == codec { Ok(stream) } else { Err(Error::Codec(theirs.id())) }
+        })
+    ),
+    };
+    let handshake = greeted
+        .and_then({ let ours = ours.clone(); move |stream| write(stream, codec, &ours) })
+        .and_then(move |stream| read::<Hello>(stream, codec))
+        .and_then(move |(stream, theirs)| {
+            if theirs.id == self_id || expected.map_or(false, |id| id != theirs.id) {
+                return Err(Error::Identity(theirs.id))
+            }
+            let transcript = match role {
+            | Role::Initiator => Transcript::new(preamble, &ours, &theirs),
+            | Role::Responder => Transcript::new(preamble, &theirs, &ours),
+            };
+            Ok((stream, theirs, transcript))
+        })
+        .and_then(move |(stream, theirs, transcript)| {
+            let id = theirs.id;
+            let compression = if theirs.compression == compression { compression } else { None };
+            let proof = Proof { mac: sign(secret.as_ref(), role, &transcript) };
+            let verify = move |(stream, proof): (Connection, Proof)| {
+                let other = match role {
+                | Role::Initiator => Role::Responder,
+                | Role::Responder => Role::Initiator,
+                };
+                if check(secret.as_ref(), other, &transcript, &proof.mac) {
+                    Ok(stream)
+                } else {
+                    Err(Error::Unauthenticated(id))
+                }
+            };
+            let authenticated = match role {
+            | Role::Initiator => future::Either::A(
+                write(stream, codec, &proof)
+                    .and_then(move |stream| read::<Proof>(stream, codec))
+                    .and_then(verify)
+            ),
+            | Role::Responder => future::Either::B(
+                read::<Proof>(stream, codec)
+                    .and_then(verify)
+                    .and_then(move |stream| write(stream, codec, &proof))
+            ),
+            };
+            authenticated.map(move |stream| (id, compression, stream))
+        });
+
+    tokio::timer::Timeout::new(handshake, timeout)
+        .map_err(|error| {
+            let error = error.into_inner().unwrap_or(Error::Timeout);
+            warn!("rejected peer connection: {}", error);
+        })
+}
+
+/// Negotiate with the client on the other end of `stream`, returning the client ID
+/// its credential proves if authentication is enabled with `auth`, and the codec
+/// the client chose to encode messages with.
+pub(crate) fn client<S: state::State>(
+    stream: Connection,
+    cluster: cluster::Cluster,
+    auth: Option<Arc<dyn auth::Auth<S::Command>>>,
+    timeout: std::time::Duration,
+) -> impl Future<Item = (Option<<S::Command as state::Command>::ClientID>, Codec, Connection), Error = ()> {
+    let handshake = answer(stream, Preamble::new::<S>(cluster))
+        .and_then(|(stream, codec)| {
+            read::<Login>(stream, codec).map(move |(stream, login)| (stream, codec, login))
+        })
+        .and_then(move |(stream, codec, login)| {
+            let identity = match &auth {
+            | None => Ok(None),
+            | Some(auth) => auth.authenticate(&login.credential).map(Some).ok_or(Error::Credential),
+            };
+            match identity {
+            | Ok(identity) => future::Either::A(
+                write(stream, codec, &Verdict::Accepted).map(move |stream| (identity, codec, stream))
+            ),
+            | Err(error) => future::Either::B(
+                write(stream, codec, &Verdict::Rejected).then(|_| Err(error))
+            ),
+            }
+        });
+
+    tokio::timer::Timeout::new(handshake, timeout)
+        .map_err(|error| {
+            let error = error.into_inner().unwrap_or(Error::Timeout);
+            warn!("rejected client connection: {}", error);
+        })
+}
+
+/// Negotiate with the server on the other end of `stream`, which must belong to
+/// `cluster` and run state machine `S`, presenting `credential` if it requires
+/// authentication. Must be called before creating channels with `external::with_codec`,
+/// using the same `codec`, which the server must support.
+pub fn connect<S, I>(stream: I, cluster: cluster::Cluster, codec: Codec, credential: Vec<u8>) -> impl Future<Item = Connection, Error = Error>
+    where S: state::State,
+          I: external::Io,
+{
+    initiate(Box::new(stream), codec, Preamble::new::<S>(cluster))
+        .and_then(move |stream| write(stream, codec, &Login { credential }))
+        .and_then(move |stream| read::<Verdict>(stream, codec))
+        .and_then(|(stream, verdict)| {
+            match verdict {
+            | Verdict::Accepted => Ok(stream),
+            | Verdict::Rejected => Err(Error::Rejected),
+            }
+        })
+}
+
+/// Open the handshake on a connection we initiated, encoding messages with `codec`,
+/// and exchange preambles, failing if the other end's differs from `ours`.
+fn initiate(stream: Connection, codec: Codec, ours: Preamble) -> impl Future<Item = Connection, Error = Error> {
+    tokio::io::write_all(stream, header(codec.id()))
+        .map_err(Error::Io)
+        .and_then({ let ours = ours.clone(); move |(stream, _)| write(stream, codec, &ours) })
+        .and_then(|stream| tokio::io::read_exact(stream, [0; HEADER]).map_err(Error::Io))
+        .and_then(move |(stream, theirs)| {
+            let version = version(&theirs);
+            if version != PROTOCOL_VERSION {
+                Err(Error::Version { ours: PROTOCOL_VERSION, theirs: version })
+            } else if theirs[4] != codec.id() {
+                Err(Error::Codec(codec.id()))
+            } else {
+                Ok(stream)
+            }
+        })
+        .and_then(move |stream| read::<Preamble>(stream, codec))
+        .and_then(move |(stream, theirs)| compare(&ours, &theirs).map(|_| stream))
+}
+
+/// Answer the handshake on a connection we accepted, and exchange preambles,
+/// failing if the other end's differs from `ours`. Returns the codec chosen
+/// by the other end.
+fn answer(stream: Connection, ours: Preamble) -> impl Future<Item = (Connection, Codec), Error = Error> {
+    tokio::io::read_exact(stream, [0; HEADER])
+        .map_err(Error::Io)
+        .and_then(|(stream, theirs)| {
+            let version = version(&theirs);
+            let codec = Codec::from_id(theirs[4]);
+
+            // Reply even if we reject the header, so that the other end can report why
+            let reply = header(codec.unwrap_or_default().id());
+            tokio::io::write_all(stream, reply)
+                .map_err(Error::Io)
+                .and_then(move |(stream, _)| {
+                    if version != PROTOCOL_VERSION {
+                        return Err(Error::Version { ours: PROTOCOL_VERSION, theirs: version })
+                    }
+                    codec.map(|codec| (stream, codec)).ok_or(Error::Codec(theirs[4]))
+                })
+        })
+        .and_then({ let ours = ours.clone(); move |(stream, codec)| write(stream, codec, &ours).map(move |stream| (stream, codec)) })
+        .and_then(|(stream, codec)| {
+            read::<Preamble>(stream, codec).map(move |(stream, theirs)| (stream, codec, theirs))
+        })
+        .and_then(move |(stream, codec, theirs)| compare(&ours, &theirs).map(|_| (stream, codec)))
+}
+
+/// Encode the header that begins every connection.
+fn header(codec: u8) -> [u8; HEADER] {
+    let mut header = [0; HEADER];
+    header[..4].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    header[4] = codec;
+    header
+}
+
+/// Decode the protocol version from a header.
+fn version(header: &[u8; HEADER]) -> u32 {
+    u32::from_be_bytes([header[0], header[1], header[2], header[3]])
+}
+
+/// Fail if the other end's preamble differs from `ours`.
+fn compare(ours: &Preamble, theirs: &Preamble) -> Result<(), Error> {
+    if theirs.cluster != ours.cluster {
+        Err(Error::Cluster { ours: ours.cluster, theirs: theirs.cluster })
+    } else if theirs.machine != ours.machine {
+        Err(Error::Machine { ours: ours.machine.clone(), theirs: theirs.machine.clone() })
+    } else {
+        Ok(())
+    }
+}
+
+/// Compute the proof that the server in `role` knows `secret` and took part in the
+/// handshake recorded by `transcript`. Empty if no secret is configured.
+fn sign(secret: Option<&Secret>, role: Role, transcript: &Transcript) -> Vec<u8> {
+    match secret {
+    | Some(secret) => hmac(secret, role, transcript).result().code().to_vec(),
+    | None => Vec::new(),
+    }
+}
+
+/// Verify in constant time that `proof` was computed by `sign`. Always succeeds
+/// if no secret is configured.
+fn check(secret: Option<&Secret>, role: Role, transcript: &Transcript, proof: &[u8]) -> bool {
+    match secret {
+    | Some(secret) => hmac(secret, role, transcript).verify(proof).is_ok(),
+    | None => true,
+    }
+}
+
+fn hmac(secret: &Secret, role: Role, transcript: &Transcript) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(&secret.0)
+        .expect("[INTERNAL ERROR]: HMAC accepts keys of any length");
+    mac.input(&bincode::serialize(&(role, transcript)).expect("[INTERNAL ERROR]: failed to serialize transcript"));
+    mac
+}
+
+/// Write a single length-prefixed frame, encoded with `codec`. Fails instead of
+/// writing a frame that the other end would reject as too large.
+fn write<T: serde::Serialize>(stream: Connection, codec: Codec, message: &T) -> impl Future<Item = Connection, Error = Error> {
+    let frame = codec.encode(message)
+        .expect("[INTERNAL ERROR]: failed to encode handshake");
+    if frame.len() > MAX_FRAME {
+        return future::Either::B(future::err(Error::Oversized(frame.len())))
+    }
+    let mut bytes = (frame.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(frame);
+    let written = tokio::io::write_all(stream, bytes)
+        .map(|(stream, _)| stream)
+        .map_err(Error::Io);
+    future::Either::A(written)
+}
+
+/// Read and decode a single length-prefixed frame, encoded with `codec`.
+fn read<T: serde::de::DeserializeOwned>(stream: Connection, codec: Codec) -> impl Future<Item = (Connection, T), Error = Error> {
+    read_frame(stream).and_then(move |(stream, bytes)| {
+        codec.decode(&bytes)
+            .map(|message| (stream, message))
+            .map_err(Error::Decode)
+    })
+}
+
+/// Read a single length-prefixed frame without decoding it.
+fn read_frame(stream: Connection) -> impl Future<Item = (Connection, Vec<u8>), Error = Error> {
+    tokio::io::read_exact(stream, [0; 4])
+        .map_err(Error::Io)
+        .and_then(|(stream, len)| {
+            let len = u32::from_be_bytes(len) as usize;
+            if len <= MAX_FRAME {
+                future::Either::A(tokio::io::read_exact(stream, vec![0; len]).map_err(Error::Io))
+            } else {
+                future::Either::B(future::err(Error::Oversized(len)))
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::transport::{Memory, Transport};
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Debug)]
+    struct Command;
+
+    impl state::Command for Command {
+        type ClientID = usize;
+        type LocalID = usize;
+        fn client_id(&self) -> usize { 0 }
+        fn local_id(&self) -> usize { 0 }
+    }
+
+    #[derive(Default)]
+    struct Machine;
+
+    impl state::State for Machine {
+        type Command = Command;
+        type Response = ();
+        fn execute(&mut self, _: usize, _: Command) -> Option<()> { None }
+        fn tag() -> &'static str { "machine" }
+    }
+
+    fn secret(secret: &[u8]) -> Option<Secret> {
+        Some(Secret::new(secret.to_vec()))
+    }
+
+    /// Accept a single peer connection on `port` as server `id`, resolving
+    /// to the ID of the peer if it authenticated.
+    fn accept(memory: &Memory, port: usize, id: usize, secret: Option<Secret>) -> impl Future<Item = Option<usize>, Error = ()> {
+        memory.listen(port)
+            .expect("[TEST ERROR]: could not listen")
+            .take(1)
+            .collect()
+            .map_err(|_| ())
+            .and_then(|mut connecting| connecting.pop().expect("[TEST ERROR]: listener closed").map_err(|_| ()))
+            .and_then(move |stream| peer::<Machine>(stream, cluster::Cluster::nil(), id, None, secret, None, TIMEOUT))
+            .then(|result| Ok(result.ok().map(|(id, _, _)| id)))
+    }
+
+    /// Dial server `expected` on `port` as server `id`, resolving to the ID
+    /// of the peer if it authenticated.
+    fn dial(memory: &Memory, port: usize, id: usize, expected: usize, secret: Option<Secret>) -> impl Future<Item = Option<usize>, Error = ()> {
+        memory.connect(port)
+            .map_err(|_| ())
+            .and_then(move |stream| peer::<Machine>(stream, cluster::Cluster::nil(), id, Some(expected), secret, None, TIMEOUT))
+            .then(|result| Ok(result.ok().map(|(id, _, _)| id)))
+    }
+
+    #[test]
+    fn accepts_clients_with_any_codec() {
+        for codec in &[Codec::Bincode, Codec::Json, Codec::MessagePack] {
+            let memory = Memory::default();
+            let accepted = memory.listen(1)
+                .expect("[TEST ERROR]: could not listen")
+                .take(1)
+                .collect()
+                .map_err(|_| ())
+                .and_then(|mut connecting| connecting.pop().expect("[TEST ERROR]: listener closed").map_err(|_| ()))
+                .and_then(|stream| client::<Machine>(stream, cluster::Cluster::nil(), None, TIMEOUT))
+                .map(|(_, codec, _)| codec);
+            let connected = memory.connect(1)
+                .map_err(|_| ())
+                .and_then(move |stream| connect::<Machine, _>(stream, cluster::Cluster::nil(), *codec, Vec::new()).map_err(|_| ()))
+                .map(|_| ());
+            let mut runtime = tokio::runtime::Runtime::new().unwrap();
+            assert_eq!(runtime.block_on(accepted.join(connected)), Ok((*codec, ())));
+        }
+    }
+
+    #[test]
+    fn authenticates_peers_with_shared_secret() {
+        let memory = Memory::default();
+        let accepted = accept(&memory, 1, 1, secret(b"secret"));
+        let dialed = dial(&memory, 1, 0, 1, secret(b"secret"));
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(runtime.block_on(accepted.join(dialed)), Ok((Some(0), Some(1))));
+    }
+
+    #[test]
+    fn rejects_peers_with_different_secret() {
+        let memory = Memory::default();
+        let accepted = accept(&memory, 1, 1, secret(b"secret"));
+        let dialed = dial(&memory, 1, 0, 1, secret(b"guess"));
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(runtime.block_on(accepted.join(dialed)), Ok((None, None)));
+    }
+
+    #[test]
+    fn rejects_relayed_proofs() {
+        let memory = Memory::default();
+        let a = accept(&memory, 0, 0, secret(b"secret"));
+        let b = accept(&memory, 1, 1, secret(b"secret"));
+
+        // Without the secret, dial each server while claiming to be the other,
+        // forward each server's challenge to the other, and relay whatever they send.
+        let preamble = Preamble::new::<Machine>(cluster::Cluster::nil());
+        let to_a = memory.connect(0)
+            .map_err(Error::Io)
+            .and_then({ let preamble = preamble.clone(); move |stream| initiate(stream, Codec::Bincode, preamble) })
+            .and_then(|stream| read::<Hello>(stream, Codec::Bincode));
+        let to_b = memory.connect(1)
+            .map_err(Error::Io)
+            .and_then(move |stream| initiate(stream, Codec::Bincode, preamble))
+            .and_then(|stream| read::<Hello>(stream, Codec::Bincode));
+        let attack = to_a.join(to_b)
+            .and_then(|((a, from_a), (b, from_b))| {
+                let a = write(a, Codec::Bincode, &Hello { id: 1, nonce: from_b.nonce, compression: None });
+                let b = write(b, Codec::Bincode, &Hello { id: 0, nonce: from_a.nonce, compression: None });
+                a.join(b)
+            })
+            .and_then(|(a, b)| {
+                let (a_rx, a_tx) = a.split();
+                let (b_rx, b_tx) = b.split();
+                tokio::io::copy(a_rx, b_tx)
+                    .join(tokio::io::copy(b_rx, a_tx))
+                    .map_err(Error::Io)
+            })
+            .then(|_| Ok(()));
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(attack);
+        assert_eq!(runtime.block_on(a.join(b)), Ok((None, None)));
+    }
+}
@@ -0,0 +1,163 @@
+//! # Summary
+//!
+//! This module decodes stable storage files offline, for debugging stuck
+//! or misbehaving clusters. Storage files are raw bincode, so decoding them
+//! requires the state machine type `S` the server was launched with.
+//!
+//! The kind of each file is determined from its name, which must start with
+//! `acceptor-`, `leader-`, or `replica-`. Commands are rendered with their
+//! `Debug` implementation, so that summaries can be printed and compared
+//! without knowing anything else about `S`.
+
+use std::collections::BTreeMap as Map;
+use std::path::Path;
+
+use serde_derive::Serialize;
+
+use crate::message;
+use crate::state;
+use crate::storage;
+use crate::thread::{acceptor, leader, replica};
+
+pub use crate::message::Ballot;
+
+/// Decoded contents of a single storage file.
+#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Summary {
+    Acceptor {
+        /// Highest ballot seen
+        ballot: Ballot,
+
+        /// Ballot and command of the most recently accepted PValue per slot
+        accepted: Map<usize, (Ballot, String)>,
+    },
+
+    Leader {
+        /// Leader's current ballot
+        ballot: Ballot,
+
+        /// Planned proposals
+        proposals: Map<usize, String>,
+
+        /// Latest known decision
+        decided: Option<usize>,
+    },
+
+    Replica {
+        /// Slot for next proposal
+        proposal_slot: usize,
+
+        /// Slot for next decision
+        decision_slot: usize,
+
+        /// Outstanding proposals
+        proposals: Map<usize, String>,
+
+        /// Decision log
+        decisions: Map<usize, String>,
+    },
+}
+
+/// Failure to decode a storage file or exported decision log.
+#[derive(Debug)]
+pub enum Error {
+    /// File name does not start with `acceptor-`, `leader-`, or `replica-`
+    Unknown(String),
+
+    /// File could not be opened or created
+    Io(std::io::Error),
+
+    /// File is truncated, corrupt, or was written for a different state machine
+    Decode(bincode::Error),
+
+    /// Exported decision log could not be read or written
+    Export(serde_json::Error),
+
+    /// Replica storage has no decision for the attached slot, which was already executed
+    Missing(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+        | Error::Unknown(name) => write!(fmt, "unknown storage file {}", name),
+        | Error::Io(error) => write!(fmt, "could not access file: {}", error),
+        | Error::Decode(error) => write!(fmt, "could not decode storage file: {}", error),
+        | Error::Export(error) => write!(fmt, "could not read or write decision log: {}", error),
+        | Error::Missing(slot) => write!(fmt, "replica storage is missing executed slot {}", slot),
+        }
+    }
+}
+
+/// Decode the storage file at `path`, written by a server with state machine `S`.
+pub fn load<S: state::State, P: AsRef<Path>>(path: P) -> Result<Summary, Error> {
+    let path = path.as_ref();
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file = std::fs::File::open(path).map_err(Error::Io)?;
+
+    if name.starts_with("acceptor-") {
+        let (_, stable): (_, acceptor::Stable<S>) = storage::decode(file).map_err(Error::Decode)?;
+        Ok(Summary::Acceptor {
+            ballot: stable.ballot,
+            accepted: stable.accepted.into_iter()
+                .map(|(slot, pvalue)| (slot, (pvalue.b_id, render(&pvalue.command))))
+                .collect(),
+        })
+    } else if name.starts_with("leader-") {
+        let (_, stable): (_, leader::Stable<S>) = storage::decode(file).map_err(Error::Decode)?;
+        Ok(Summary::Leader {
+            ballot: stable.ballot,
+            proposals: stable.proposals.iter()
+                .map(|(slot, command)| (*slot, render(command)))
+                .collect(),
+            decided: stable.decided,
+        })
+    } else if name.starts_with("replica-") {
+        let (_, stable): (_, replica::Stable<S>) = storage::decode(file).map_err(Error::Decode)?;
+        Ok(Summary::Replica {
+            proposal_slot: stable.proposal_slot,
+            decision_slot: stable.decision_slot,
+            proposals: stable.proposals.iter()
+                .map(|(slot, command)| (*slot, render(command)))
+                .collect(),
+            decisions: stable.decisions.iter()
+                .map(|(slot, command)| (*slot, render(command)))
+                .collect(),
+        })
+    } else {
+        Err(Error::Unknown(name))
+    }
+}
+
+/// Compare the per-slot contents of several summaries of the same kind: accepted
+/// PValues for acceptors, proposals for leaders, and decisions for replicas.
+/// Returns every slot where they disagree, along with each summary's entry.
+pub fn diff(summaries: &[Summary]) -> Map<usize, Vec<Option<String>>> {
+    let slots = summaries.iter()
+        .map(|summary| match summary {
+        | Summary::Acceptor { accepted, .. } => accepted.iter()
+            .map(|(slot, (ballot, command))| (*slot, format!("{:?} {}", ballot, command)))
+            .collect(),
+        | Summary::Leader { proposals, .. } => proposals.clone(),
+        | Summary::Replica { decisions, .. } => decisions.clone(),
+        })
+        .collect::<Vec<Map<usize, String>>>();
+
+    let mut diff = Map::default();
+    for slot in slots.iter().flat_map(|entries| entries.keys()) {
+        let entries = slots.iter()
+            .map(|entries| entries.get(slot).cloned())
+            .collect::<Vec<_>>();
+        if entries.iter().any(|entry| *entry != entries[0]) {
+            diff.insert(*slot, entries);
+        }
+    }
+    diff
+}
+
+fn render<C: state::Command>(command: &message::Command<C>) -> String {
+    format!("{:?}", &**command)
+}
@@ -20,11 +20,14 @@
 //!
 //! ## **Important**
 //!
-//! Currently, servers listen for clients using TCP streams, and communicate using
-//! length-delimited `bincode`-encoded Rust data. For convenience, `Sink` and `Stream`
-//! implementations of the receiving and transmitting wrappers around `TcpStream`
-//! are exposed as `external::Rx<T>` and `external::Tx<T>`, respectively, and they
-//! can be created from a Tokio `TcpStream` using `external::new`.
+//! By default, servers listen for clients using TCP streams, or any other
+//! `transport::Transport`, and communicate using length-delimited Rust data, encoded
+//! with `bincode` unless the client negotiates another `Codec`. For convenience,
+//! `Sink` and `Stream` implementations of the receiving and transmitting wrappers around
+//! these streams are exposed as `external::Rx<T>` and `external::Tx<T>`, respectively,
+//! and they can be created from a Tokio `TcpStream` using `external::with_codec`. Clients
+//! must first negotiate the connection with `handshake::connect`, after completing a TLS
+//! handshake with `tls::Tls` if the server is configured with TLS.
 //!
 //! # Example
 //!
@@ -88,6 +91,9 @@
 //!         },
 //!         }
 //!     }
+//!     fn tag() -> &'static str {
+//!         "chatroom"
+//!     }
 //! }
 //! ```
 //!
@@ -113,21 +119,57 @@
 #[macro_use] extern crate log;
 #[macro_use] extern crate tokio;
 
+/// Authenticating and authorizing clients.
+mod auth;
+
+/// Identifying clusters.
+mod cluster;
+
+/// Encoding frames on external channels.
+pub mod codec;
+
 /// Configuring and starting a Paxos replica.
 mod config;
 
 /// Communicating with external processes.
 pub mod external;
 
+/// Injecting faults for testing.
+mod fault;
+
+/// Authenticating peer and client connections.
+pub mod handshake;
+
+/// Collecting and exporting runtime metrics.
+mod metrics;
+
+/// Decoding stable storage files offline.
+pub mod inspect;
+
 /// Communicating with internal threads.
 mod internal;
 
 /// Message types for communication.
 mod message;
 
+/// Exporting and replaying decision logs.
+pub mod replay;
+
+/// Pluggable network, clock, and randomness.
+mod runtime;
+
+/// Deterministic cluster simulation.
+pub mod simulate;
+
+/// Structured tracing of protocol events.
+mod span;
+
 /// User-defined state machine.
 mod state;
 
+/// Summarizing server state for operators.
+mod status;
+
 /// Thread-safe internal transmission hub.
 mod shared;
 
@@ -137,5 +179,15 @@ mod storage;
 /// Paxos protocol threads.
 mod thread;
 
+/// Encrypting connections with TLS.
+pub mod tls;
+
+/// Listening for and establishing connections.
+pub mod transport;
+
+pub use crate::auth::Auth;
+pub use crate::cluster::Cluster;
+pub use crate::codec::{Codec, Compression};
 pub use crate::config::Config;
+pub use crate::fault::Fault;
 pub use crate::state::{Identifier, Command, Response, State};
@@ -135,6 +135,20 @@ pub struct P2B {
     pub b_id: Ballot,
 }
 
+/// Digest of a replica's state after executing all slots before `s_id`.
+#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Digest {
+    /// Replica ID
+    pub r_id: usize,
+
+    /// Next slot to execute
+    pub s_id: usize,
+
+    /// Digest of the replica's state
+    pub digest: u64,
+}
+
 /// Represents a proposed binding from slot to command.
 #[derive(Serialize, Deserialize)]
 #[serde(bound(serialize = "", deserialize = ""))]
@@ -0,0 +1,191 @@
+//! # Summary
+//!
+//! This module collects runtime metrics for a single server, and renders
+//! them in the Prometheus text exposition format.
+//!
+//! Metrics are updated with relaxed atomics from every thread through the
+//! shared transmission hub, and can be scraped over HTTP by enabling
+//! `Config::with_metrics_port`.
+
+use std::collections::BTreeMap as Map;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time;
+
+use parking_lot::Mutex;
+
+/// Upper bounds of storage write latency buckets (in seconds)
+const BUCKETS: [f64; 10] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1];
+
+/// Monotonically increasing count.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicUsize);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Value that can go up and down.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicUsize);
+
+impl Gauge {
+    pub fn set(&self, value: usize) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Distribution of durations over the fixed `BUCKETS`.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    /// Number of observations at most each bucket's bound
+    buckets: [AtomicUsize; 10],
+
+    /// Total number of observations
+    count: AtomicUsize,
+
+    /// Sum of all observations (in microseconds)
+    sum: AtomicUsize,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: time::Duration) {
+        let seconds = duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9;
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS.iter()) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add((seconds * 1e6) as usize, Ordering::Relaxed);
+    }
+}
+
+/// Metrics collected by a single server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Ballots adopted by a majority of acceptors
+    pub ballots_adopted: Counter,
+
+    /// Ballots preempted by a higher ballot
+    pub preemptions: Counter,
+
+    /// Commanders spawned by the leader
+    pub commanders_spawned: Counter,
+
+    /// Slots executed by the replica
+    pub slots_decided: Counter,
+
+    /// Peer state digests that disagree with this replica's
+    pub divergences: Counter,
+
+    /// Frames from peers and clients that could not be decoded
+    pub malformed_frames: Counter,
+
+    /// Decided slots waiting on an earlier gap before they can be executed
+    pub decision_lag: Gauge,
+
+    /// Client requests proposed by the replica but not yet decided
+    pub client_queue_depth: Gauge,
+
+    /// Sequence number of the leader's current ballot
+    pub ballot: Gauge,
+
+    /// Leader ID of the leader's current ballot
+    pub ballot_leader: Gauge,
+
+    /// Whether the leader has been adopted by a majority of acceptors
+    pub leader_active: Gauge,
+
+    /// Next slot the replica will execute
+    pub decision_slot: Gauge,
+
+    /// Next slot the replica will propose
+    pub proposal_slot: Gauge,
+
+    /// Time taken to write stable storage
+    pub storage_writes: Histogram,
+
+    /// Connection state of each known peer
+    peers: Mutex<Map<usize, bool>>,
+}
+
+impl Metrics {
+    /// Record that peer `id` is now connected or disconnected.
+    pub fn set_peer(&self, id: usize, connected: bool) {
+        self.peers.lock().insert(id, connected);
+    }
+
+    /// Returns the IDs of all currently connected peers.
+    pub fn peers(&self) -> Vec<usize> {
+        self.peers.lock()
+            .iter()
+            .filter(|(_, connected)| **connected)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn export(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("paxos_ballots_adopted_total", "Ballots adopted by a majority of acceptors", &self.ballots_adopted),
+            ("paxos_preemptions_total", "Ballots preempted by a higher ballot", &self.preemptions),
+            ("paxos_commanders_spawned_total", "Commanders spawned by the leader", &self.commanders_spawned),
+            ("paxos_slots_decided_total", "Slots executed by the replica", &self.slots_decided),
+            ("paxos_divergences_total", "Peer state digests that disagree with this replica", &self.divergences),
+            ("paxos_malformed_frames_total", "Frames from peers and clients that could not be decoded", &self.malformed_frames),
+        ];
+        for (name, help, counter) in &counters {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{} {}", name, counter.get()).unwrap();
+        }
+
+        let gauges = [
+            ("paxos_decision_lag", "Decided slots waiting on an earlier gap", &self.decision_lag),
+            ("paxos_client_queue_depth", "Client requests proposed but not yet decided", &self.client_queue_depth),
+            ("paxos_ballot", "Sequence number of the current ballot", &self.ballot),
+            ("paxos_ballot_leader", "Leader ID of the current ballot", &self.ballot_leader),
+            ("paxos_leader_active", "Whether the leader is adopted", &self.leader_active),
+            ("paxos_decision_slot", "Next slot to execute", &self.decision_slot),
+            ("paxos_proposal_slot", "Next slot to propose", &self.proposal_slot),
+        ];
+        for (name, help, gauge) in &gauges {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            writeln!(out, "{} {}", name, gauge.get()).unwrap();
+        }
+
+        let name = "paxos_storage_write_seconds";
+        writeln!(out, "# HELP {} Time taken to write stable storage", name).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        for (bucket, bound) in self.storage_writes.buckets.iter().zip(BUCKETS.iter()) {
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, bucket.load(Ordering::Relaxed)).unwrap();
+        }
+        let count = self.storage_writes.count.load(Ordering::Relaxed);
+        let sum = self.storage_writes.sum.load(Ordering::Relaxed) as f64 / 1e6;
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count).unwrap();
+        writeln!(out, "{}_sum {}", name, sum).unwrap();
+        writeln!(out, "{}_count {}", name, count).unwrap();
+
+        let name = "paxos_peer_connected";
+        writeln!(out, "# HELP {} Whether each peer is currently connected", name).unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+        for (id, connected) in self.peers.lock().iter() {
+            writeln!(out, "{}{{peer=\"{}\"}} {}", name, id, *connected as usize).unwrap();
+        }
+
+        out
+    }
+}
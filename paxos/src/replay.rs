@@ -0,0 +1,89 @@
+//! # Summary
+//!
+//! This module exports a replica's decision log to a portable JSON file, and
+//! replays exported logs into a fresh state machine. Replaying the logs of
+//! several replicas and comparing the resulting states checks that they
+//! actually computed the same thing.
+//!
+//! Only the executed prefix of the decision log is exported. Replay rebuilds
+//! the state with the same routine that a restarting `Replica` recovers with,
+//! skipping any command that was already decided in an earlier slot.
+
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::inspect::Error;
+use crate::message;
+use crate::state;
+use crate::storage;
+use crate::thread::replica;
+
+/// Ordered commands decided in slots `0..commands.len()`.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+#[derive(Clone, Debug)]
+pub struct Log<C: state::Command> {
+    pub commands: Vec<C>,
+}
+
+impl<C: state::Command> Log<C> {
+    /// Read an exported log from `path`.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = std::fs::File::open(path).map_err(Error::Io)?;
+        serde_json::from_reader(file).map_err(Error::Export)
+    }
+
+    /// Write this log to `path` as JSON.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let file = std::fs::File::create(path).map_err(Error::Io)?;
+        serde_json::to_writer_pretty(file, self).map_err(Error::Export)
+    }
+
+    /// Only keep the first `len` slots.
+    pub fn truncate(&mut self, len: usize) {
+        self.commands.truncate(len);
+    }
+}
+
+/// Export the executed decisions in replica storage file `path`, written by a
+/// server with state machine `S`.
+pub fn export<S: state::State, P: AsRef<Path>>(path: P) -> Result<Log<S::Command>, Error> {
+    let file = std::fs::File::open(path).map_err(Error::Io)?;
+    let (_, stable): (_, replica::Stable<S>) = storage::decode(file).map_err(Error::Decode)?;
+    let commands = (0..stable.decision_slot)
+        .map(|slot| {
+            stable.decisions.get(&slot)
+                .map(|command| command.clone().inner())
+                .ok_or(Error::Missing(slot))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Log { commands })
+}
+
+/// Execute every command in `log` on a fresh state machine.
+pub fn replay<S: state::State>(log: &Log<S::Command>) -> S {
+    replica::rebuild(log.commands.iter().cloned().map(message::Command::from))
+}
+
+/// Replay every log up to the length of the shortest one, returning the index of
+/// the first log whose final state differs from that of the first log.
+pub fn verify<S>(logs: &[Log<S::Command>]) -> Option<usize>
+    where S: state::State + serde::Serialize
+{
+    let len = logs.iter()
+        .map(|log| log.commands.len())
+        .min()
+        .unwrap_or(0);
+
+    let states = logs.iter()
+        .map(|log| {
+            let mut log = log.clone();
+            log.truncate(len);
+            bincode::serialize(&replay::<S>(&log))
+                .expect("[INTERNAL ERROR]: failed to serialize state")
+        })
+        .collect::<Vec<_>>();
+
+    states.iter().position(|state| *state != states[0])
+}
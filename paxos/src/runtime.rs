@@ -0,0 +1,110 @@
+//! # Summary
+//!
+//! This module abstracts over the environment that protocol threads run in:
+//! the network used to reach peer servers, the clock used to drive timeouts,
+//! and the source of randomness used for leader backoff.
+//!
+//! Real servers use `Tcp`, `Tokio`, and `Thread`, respectively. The `simulate`
+//! module substitutes deterministic implementations so that an entire cluster
+//! can be run reproducibly from a single seed.
+
+use std::collections::BTreeMap as Map;
+use std::time;
+
+use tokio::prelude::*;
+
+use crate::internal;
+use crate::state;
+use crate::thread::peer;
+
+/// Periodic timer that yields once per elapsed period.
+pub type Interval = Box<dyn Stream<Item = (), Error = ()> + Send>;
+
+/// One-shot timer that completes once its delay has elapsed.
+pub type Delay = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+/// Source of timers for scouts, commanders, and peers.
+pub trait Clock: Send + Sync {
+    /// Creates a timer that first fires after `delay`, and then every `period`.
+    fn interval(&self, delay: time::Duration, period: time::Duration) -> Interval;
+
+    /// Creates a timer that fires once after `delay`.
+    fn delay(&self, delay: time::Duration) -> Delay {
+        let first = self.interval(delay, delay)
+            .into_future()
+            .map(|_| ())
+            .map_err(|_| ());
+        Box::new(first)
+    }
+}
+
+/// Source of randomness for leaders.
+pub trait Random: Send + Sync {
+    /// Returns a uniformly distributed number in `[0, 1)`.
+    fn random(&self) -> f32;
+}
+
+/// Outgoing connections to peer servers.
+pub trait Network<C: state::Command>: Send + Sync {
+    /// Registers the provided peer channel.
+    fn connect(&mut self, id: usize, tx: internal::Tx<peer::In<C>>);
+
+    /// Disconnects the provided peer.
+    fn disconnect(&mut self, id: usize);
+
+    /// Sends a message to the provided peer, if it is reachable.
+    fn send(&self, id: usize, message: peer::In<C>);
+
+    /// Sends a message to all reachable peers.
+    fn broadcast(&self, message: peer::In<C>);
+}
+
+/// Wall-clock timers backed by `tokio::timer`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Tokio;
+
+impl Clock for Tokio {
+    fn interval(&self, delay: time::Duration, period: time::Duration) -> Interval {
+        let interval = tokio::timer::Interval::new(time::Instant::now() + delay, period)
+            .map(|_| ())
+            .map_err(|_| ());
+        Box::new(interval)
+    }
+}
+
+/// Thread-local randomness backed by `rand::random`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Thread;
+
+impl Random for Thread {
+    fn random(&self) -> f32 {
+        rand::random::<f32>()
+    }
+}
+
+/// Peer connections backed by `thread::peer` streams over the configured transport.
+#[derive(Derivative)]
+#[derivative(Default(bound = ""))]
+pub struct Tcp<C: state::Command>(Map<usize, internal::Tx<peer::In<C>>>);
+
+impl<C: state::Command> Network<C> for Tcp<C> {
+    fn connect(&mut self, id: usize, tx: internal::Tx<peer::In<C>>) {
+        self.0.insert(id, tx);
+    }
+
+    fn disconnect(&mut self, id: usize) {
+        self.0.remove(&id);
+    }
+
+    fn send(&self, id: usize, message: peer::In<C>) {
+        if let Some(tx) = self.0.get(&id) {
+            tx.try_send(message);
+        }
+    }
+
+    fn broadcast(&self, message: peer::In<C>) {
+        for tx in self.0.values() {
+            tx.try_send(message.clone());
+        }
+    }
+}
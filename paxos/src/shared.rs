@@ -5,13 +5,21 @@
 //! to share the connections between concurrently running threads.
 
 use std::collections::HashMap as Map;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time;
 
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+use crate::cluster;
+use crate::fault;
 use crate::internal;
 use crate::message;
+use crate::metrics;
+use crate::runtime;
+use crate::span;
 use crate::state;
+use crate::status;
 use crate::thread::{acceptor, commander, peer, replica, scout};
 
 /// Thread-safe wrapper around `State` forwarding hub.
@@ -21,15 +29,18 @@ pub struct Shared<S: state::State>(Arc<RwLock<State<S>>>);
 
 impl<S: state::State> Shared<S> {
 
-    /// Initializes a message hub with the provided transmission channels.
+    /// Initializes a message hub with the provided environment and transmission channels.
     pub fn new(
         id: usize,
+        network: Box<dyn runtime::Network<S::Command>>,
+        clock: Arc<dyn runtime::Clock>,
+        random: Arc<dyn runtime::Random>,
         scout_tx: internal::Tx<scout::In<S::Command>>,
         replica_tx: internal::Tx<replica::In<S::Command>>,
         acceptor_tx: internal::Tx<acceptor::In<S::Command>>,
     ) -> Self {
         Shared(Arc::new(RwLock::new(
-            State::new(id, scout_tx, replica_tx, acceptor_tx)
+            State::new(id, network, clock, random, scout_tx, replica_tx, acceptor_tx)
         )))
     }
 
@@ -47,7 +58,14 @@ impl<S: state::State> Shared<S> {
 /// Collection of internal transmitting channels.
 pub struct State<S: state::State> {
     id: usize,
-    peer_txs: Map<usize, internal::Tx<peer::In<S::Command>>>,
+    cluster: cluster::Cluster,
+    storage: PathBuf,
+    network: Box<dyn runtime::Network<S::Command>>,
+    clock: Arc<dyn runtime::Clock>,
+    random: Arc<dyn runtime::Random>,
+    faults: Map<usize, fault::Link>,
+    metrics: Arc<metrics::Metrics>,
+    tracer: Option<Arc<span::Tracer>>,
     client_txs: Map<<S::Command as state::Command>::ClientID, internal::Tx<S::Response>>,
     commander_txs: Map<message::CommanderID, internal::Tx<commander::In>>,
     scout_tx: internal::Tx<scout::In<S::Command>>,
@@ -57,16 +75,26 @@ pub struct State<S: state::State> {
 
 impl<S: state::State> State<S> {
 
-    /// Initializes a message hub with the provided transmission channels.
+    /// Initializes a message hub with the provided environment and transmission channels.
     pub fn new(
         id: usize,
+        network: Box<dyn runtime::Network<S::Command>>,
+        clock: Arc<dyn runtime::Clock>,
+        random: Arc<dyn runtime::Random>,
         scout_tx: internal::Tx<scout::In<S::Command>>,
         replica_tx: internal::Tx<replica::In<S::Command>>,
         acceptor_tx: internal::Tx<acceptor::In<S::Command>>,
     ) -> Self {
         State {
             id,
-            peer_txs: Map::default(),
+            cluster: cluster::Cluster::nil(),
+            storage: PathBuf::new(),
+            network,
+            clock,
+            random,
+            faults: Map::default(),
+            metrics: Arc::default(),
+            tracer: None,
             client_txs: Map::default(),
             commander_txs: Map::default(),
             scout_tx,
@@ -77,12 +105,14 @@ impl<S: state::State> State<S> {
 
     /// Registers the provided peer channel with this hub.
     pub fn connect_peer(&mut self, id: usize, tx: internal::Tx<peer::In<S::Command>>) {
-        self.peer_txs.insert(id, tx);
+        self.network.connect(id, tx);
+        self.metrics.set_peer(id, true);
     }
 
     /// Disconnects the provided peer from this hub.
     pub fn disconnect_peer(&mut self, id: usize) {
-        self.peer_txs.remove(&id);
+        self.network.disconnect(id);
+        self.metrics.set_peer(id, false);
     }
 
     /// Registers the provided client channel with this hub.
@@ -110,6 +140,96 @@ impl<S: state::State> State<S> {
         std::mem::replace(&mut self.scout_tx, tx);
     }
 
+    /// Injects a fault into the outgoing connections of this server.
+    pub fn inject(&mut self, fault: fault::Fault) {
+        info!("injecting {:?}", fault);
+        match fault {
+        | fault::Fault::Block(id) => self.faults.entry(id).or_default().blocked = true,
+        | fault::Fault::Delay(id, delay) => self.faults.entry(id).or_default().delay = delay,
+        | fault::Fault::Drop(id, drop_rate) => self.faults.entry(id).or_default().drop_rate = drop_rate,
+        | fault::Fault::Heal => self.faults.clear(),
+        }
+    }
+
+    /// Returns the faults currently injected into the outgoing connection to peer `id`.
+    pub fn link(&self, id: usize) -> fault::Link {
+        self.faults.get(&id).cloned().unwrap_or_default()
+    }
+
+    /// Returns the metrics collected by this server.
+    pub fn metrics(&self) -> &Arc<metrics::Metrics> {
+        &self.metrics
+    }
+
+    /// Records that this server belongs to `cluster`.
+    pub fn join(&mut self, cluster: cluster::Cluster) {
+        self.cluster = cluster;
+    }
+
+    /// Returns the cluster this server belongs to.
+    pub fn cluster(&self) -> cluster::Cluster {
+        self.cluster
+    }
+
+    /// Keeps all subsequently opened stable storage files in directory `dir`.
+    pub fn store_in(&mut self, dir: PathBuf) {
+        self.storage = dir;
+    }
+
+    /// Returns the path to the stable storage file of `thread` on this server.
+    pub fn storage(&self, thread: &str) -> PathBuf {
+        self.storage.join(format!("{}-{:>02}.paxos", thread, self.id))
+    }
+
+    /// Writes all subsequently opened spans to `tracer`.
+    pub fn trace_to(&mut self, tracer: Arc<span::Tracer>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Opens a new span of kind `name` with no fields.
+    pub fn span(&self, name: &'static str) -> span::Span {
+        span::Span::new(name, self.tracer.clone())
+    }
+
+    /// Summarizes the current state of this server.
+    pub fn status(&self) -> status::Status {
+        let storage = ["acceptor", "leader", "replica"].iter()
+            .map(|thread| self.storage(thread))
+            .filter_map(|file| {
+                let size = std::fs::metadata(&file).ok()?.len();
+                Some((file.display().to_string(), size))
+            })
+            .collect();
+        status::Status {
+            id: self.id,
+            ballot: message::Ballot {
+                b_id: self.metrics.ballot.get(),
+                l_id: self.metrics.ballot_leader.get(),
+            },
+            active: self.metrics.leader_active.get() != 0,
+            decision_slot: self.metrics.decision_slot.get(),
+            proposal_slot: self.metrics.proposal_slot.get(),
+            peers: self.metrics.peers(),
+            clients: self.client_txs.len(),
+            storage,
+        }
+    }
+
+    /// Creates a timer that first fires after `delay`, and then every `period`.
+    pub fn interval(&self, delay: time::Duration, period: time::Duration) -> runtime::Interval {
+        self.clock.interval(delay, period)
+    }
+
+    /// Creates a timer that fires once after `delay`.
+    pub fn delay(&self, delay: time::Duration) -> runtime::Delay {
+        self.clock.delay(delay)
+    }
+
+    /// Returns a uniformly distributed number in `[0, 1)`.
+    pub fn random(&self) -> f32 {
+        self.random.random()
+    }
+
     /// Forwards a message to the provided commander.
     pub fn send_commander(&self, c_id: message::CommanderID, message: commander::In) {
         if let Some(tx) = self.commander_txs.get(&c_id) {
@@ -145,8 +265,8 @@ impl<S: state::State> State<S> {
     pub fn send(&self, id: usize, message: peer::In<S::Command>) {
         if id == self.id {
             self.forward(message);
-        } else if let Some(tx) = self.peer_txs.get(&id) {
-            tx.try_send(message);
+        } else {
+            self.network.send(id, message);
         }
     }
 
@@ -158,6 +278,7 @@ impl<S: state::State> State<S> {
         | peer::In::P2A(c_id, p2a) => self.send_acceptor(acceptor::In::P2A(c_id, p2a)),
         | peer::In::P2B(c_id, p2b) => self.send_commander(c_id, p2b),
         | peer::In::Decision(proposal) => self.send_replica(replica::In::Decision(proposal)),
+        | peer::In::Digest(digest) => self.send_replica(replica::In::Digest(digest)),
         | peer::In::Ping(_) => (),
         }
     }
@@ -173,9 +294,7 @@ impl<S: state::State> State<S> {
 
     /// Forwards a message to all connected peer servers.
     pub fn broadcast(&self, message: peer::In<S::Command>) {
-        for tx in self.peer_txs.values() {
-            tx.try_send(message.clone());
-        }
+        self.network.broadcast(message.clone());
         self.forward(message);
     }
 }
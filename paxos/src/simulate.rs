@@ -0,0 +1,485 @@
+//! # Summary
+//!
+//! This module implements a deterministic, single-threaded simulator that runs
+//! an entire cluster within one process. Compared to a real deployment:
+//!
+//! - Protocol threads are polled by a custom executor instead of Tokio's
+//! - Timers are driven by a virtual clock instead of wall-clock time
+//! - Peer messages are delivered in random order, and optionally dropped
+//! - All randomness is drawn from a single seeded generator
+//!
+//! Given the same seed and the same sequence of calls, a simulation will
+//! always produce the same execution, so failures can be reproduced exactly.
+//!
+//! Acceptors, leaders, and replicas still persist their state to `*.paxos`
+//! files, so that crashed servers can be restarted. Each simulation keeps its
+//! files in its own temporary directory, which is removed when it is dropped.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time;
+
+use futures::executor;
+use futures::task;
+use parking_lot::Mutex;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use tokio::prelude::*;
+
+use crate::cluster;
+use crate::config;
+use crate::internal;
+use crate::runtime;
+use crate::shared;
+use crate::state;
+use crate::state::Command;
+use crate::thread::{peer, replica};
+
+/// Number of simulations created by this process, used to name storage directories
+static SIMULATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Future spawned by a protocol thread.
+type Task = executor::Spawn<Box<dyn Future<Item = (), Error = ()> + Send>>;
+
+/// Deterministic in-process cluster of `count` servers with state type `S`.
+pub struct Simulation<S: state::State> {
+    /// Total number of servers
+    count: usize,
+
+    /// Timeout for resending messages to unresponsive servers
+    timeout: time::Duration,
+
+    /// Probability that a peer message is dropped in transit
+    drop_rate: f64,
+
+    /// Directory for stable storage files
+    storage: PathBuf,
+
+    /// Seeded source of all randomness
+    random: Arc<Seeded>,
+
+    /// Virtual clock
+    clock: Virtual,
+
+    /// Peer messages in transit
+    wire: Arc<Mutex<Vec<Envelope<S::Command>>>>,
+
+    /// Tasks that have been notified and are ready to be polled
+    ready: Arc<Ready>,
+
+    /// Live tasks, tagged with the server that spawned them
+    tasks: BTreeMap<usize, (usize, Task)>,
+
+    /// Next unused task ID
+    next: usize,
+
+    /// Running servers, indexed by ID
+    servers: Vec<Option<Server<S>>>,
+
+    /// Client responses received so far, tagged with the responding server
+    responses: Arc<Mutex<Vec<(usize, S::Response)>>>,
+}
+
+/// Handles to a single running server.
+struct Server<S: state::State> {
+    /// Internal shared transmitting channels
+    shared_tx: shared::Shared<S>,
+
+    /// Internal replica transmitting channel
+    replica_tx: internal::Tx<replica::In<S::Command>>,
+
+    /// Internal transmitting channel for client responses
+    client_tx: internal::Tx<S::Response>,
+}
+
+impl<S: state::State> Simulation<S> {
+
+    /// Create a new simulation of `count` servers, with all scheduling decisions
+    /// derived from `seed`. No servers are started until `start` is called.
+    pub fn new(seed: u64, count: usize) -> Self {
+        let storage = std::env::temp_dir().join(format!(
+            "paxos-simulation-{}-{}",
+            std::process::id(),
+            SIMULATIONS.fetch_add(1, Ordering::SeqCst),
+        ));
+        std::fs::remove_dir_all(&storage).ok();
+        std::fs::create_dir_all(&storage)
+            .expect("[SIMULATION ERROR]: could not create storage directory");
+        Simulation {
+            count,
+            timeout: time::Duration::from_secs(1),
+            drop_rate: 0.0,
+            storage,
+            random: Arc::new(Seeded(Mutex::new(StdRng::seed_from_u64(seed)))),
+            clock: Virtual::default(),
+            wire: Arc::new(Mutex::new(Vec::new())),
+            ready: Arc::new(Ready::default()),
+            tasks: BTreeMap::default(),
+            next: 0,
+            servers: (0..count).map(|_| None).collect(),
+            responses: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Configure timeout duration for resending messages.
+    pub fn with_timeout(mut self, timeout: time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Configure probability in `[0, 1]` of dropping each peer message.
+    pub fn with_drop_rate(mut self, drop_rate: f64) -> Self {
+        self.drop_rate = drop_rate;
+        self
+    }
+
+    /// Current virtual time, measured from the start of the simulation.
+    pub fn now(&self) -> time::Duration {
+        self.clock.0.lock().now
+    }
+
+    /// Start server `id`, recovering from stable storage if it has previously crashed.
+    /// Does nothing if the server is already running.
+    pub fn start(&mut self, id: usize) {
+        if self.servers[id].is_some() { return }
+        debug!("starting server {}", id);
+        let count = self.count;
+        let timeout = self.timeout;
+        let network = Box::new(Wire {
+            id,
+            count,
+            wire: self.wire.clone(),
+        });
+        let clock = Arc::new(self.clock.clone());
+        let random = self.random.clone();
+        let storage = self.storage.clone();
+        let responses = self.responses.clone();
+        let server = self.enter(id, move |_| {
+            let (shared_tx, _, replica_tx) = config::spawn::<S>(
+                id,
+                count,
+                timeout,
+                network,
+                clock,
+                random,
+                &storage,
+                cluster::Cluster::nil(),
+                None,
+            );
+            let (client_rx, client_tx) = internal::new();
+            tokio::spawn(client_rx.for_each(move |response| {
+                responses.lock().push((id, response));
+                Ok(())
+            }));
+            Server {
+                shared_tx,
+                replica_tx,
+                client_tx,
+            }
+        });
+        self.servers[id] = Some(server);
+    }
+
+    /// Crash server `id`, dropping all of its threads and any state not
+    /// yet persisted to stable storage.
+    pub fn crash(&mut self, id: usize) {
+        debug!("crashing server {}", id);
+        self.servers[id] = None;
+        let crashed = self.tasks.iter()
+            .filter(|(_, (s_id, _))| *s_id == id)
+            .map(|(t_id, _)| *t_id)
+            .collect::<Vec<_>>();
+        for t_id in crashed {
+            self.tasks.remove(&t_id);
+        }
+    }
+
+    /// Submit a client request to server `id`. Responses are delivered to
+    /// the connected server, and can be retrieved with `responses`.
+    /// Does nothing if the server is not running.
+    pub fn request(&mut self, id: usize, command: S::Command) {
+        if let Some(server) = &self.servers[id] {
+            server.shared_tx
+                .write()
+                .connect_client(command.client_id(), server.client_tx.clone());
+            server.replica_tx.send(replica::In::Request(command));
+        }
+    }
+
+    /// Take all client responses received since the last call,
+    /// along with the ID of the server that sent each one.
+    pub fn responses(&mut self) -> Vec<(usize, S::Response)> {
+        std::mem::replace(&mut *self.responses.lock(), Vec::new())
+    }
+
+    /// Run until `duration` of virtual time has elapsed.
+    pub fn run_for(&mut self, duration: time::Duration) {
+        let until = self.now() + duration;
+        loop {
+            let idle = self.ready.0.lock().is_empty()
+                && self.wire.lock().is_empty();
+            if !idle {
+                self.step();
+                continue
+            }
+            match self.clock.deadline() {
+            | Some(deadline) if deadline <= until => { self.advance(); }
+            | _ => break,
+            }
+        }
+        let mut timeline = self.clock.0.lock();
+        timeline.now = std::cmp::max(timeline.now, until);
+    }
+
+    /// Perform a single scheduling decision: poll a ready task if there is one,
+    /// otherwise deliver a random in-flight message, otherwise advance the clock
+    /// to the next timer. Returns false if there was nothing left to do.
+    pub fn step(&mut self) -> bool {
+        let next = self.ready.0.lock().pop_front();
+        if let Some(t_id) = next {
+            self.poll(t_id);
+            return true
+        }
+        self.deliver() || self.advance()
+    }
+
+    /// Poll the provided task once, dropping it if it has completed.
+    fn poll(&mut self, t_id: usize) {
+        let (s_id, mut task) = match self.tasks.remove(&t_id) {
+        | Some(task) => task,
+        | None => return,
+        };
+        let notify = executor::NotifyHandle::from(self.ready.clone());
+        let result = self.enter(s_id, |_| task.poll_future_notify(&notify, t_id));
+        if let Ok(Async::NotReady) = result {
+            self.tasks.insert(t_id, (s_id, task));
+        }
+    }
+
+    /// Deliver or drop a random in-flight peer message.
+    fn deliver(&mut self) -> bool {
+        let envelope = {
+            let mut wire = self.wire.lock();
+            if wire.is_empty() { return false }
+            let index = self.random.range(wire.len());
+            wire.swap_remove(index)
+        };
+        if self.random.chance(self.drop_rate) {
+            trace!("dropping {:?} from {} to {}", envelope.message, envelope.from, envelope.to);
+            return true
+        }
+        if let Some(server) = &self.servers[envelope.to] {
+            trace!("delivering {:?} from {} to {}", envelope.message, envelope.from, envelope.to);
+            server.shared_tx.read().forward(envelope.message);
+        }
+        true
+    }
+
+    /// Advance the virtual clock to the next timer deadline, and notify all expired timers.
+    fn advance(&mut self) -> bool {
+        let expired = {
+            let mut timeline = self.clock.0.lock();
+            let deadline = match timeline.timers.values().map(|(deadline, _)| *deadline).min() {
+            | Some(deadline) => deadline,
+            | None => return false,
+            };
+            timeline.now = std::cmp::max(timeline.now, deadline);
+            let now = timeline.now;
+            let expired = timeline.timers.iter()
+                .filter(|(_, (deadline, _))| *deadline <= now)
+                .map(|(timer, _)| *timer)
+                .collect::<Vec<_>>();
+            expired.into_iter()
+                .filter_map(|timer| timeline.timers.remove(&timer))
+                .collect::<Vec<_>>()
+        };
+        for (_, task) in expired {
+            task.notify();
+        }
+        true
+    }
+
+    /// Run `f` with this simulation installed as the default executor, so that
+    /// any futures spawned with `tokio::spawn` are attributed to server `s_id`.
+    fn enter<F, R>(&mut self, s_id: usize, f: F) -> R where F: FnOnce(&mut Self) -> R {
+        let mut spawner = Spawner {
+            s_id,
+            spawned: Vec::new(),
+        };
+        let mut enter = tokio_executor::enter()
+            .expect("[SIMULATION ERROR]: cannot run simulation within an executor");
+        let result = tokio_executor::with_default(&mut spawner, &mut enter, |_| f(self));
+        for (s_id, future) in spawner.spawned {
+            let t_id = self.next;
+            self.next += 1;
+            self.tasks.insert(t_id, (s_id, executor::spawn(future)));
+            self.ready.0.lock().push_back(t_id);
+        }
+        result
+    }
+}
+
+impl<S: state::State> Drop for Simulation<S> {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.storage).ok();
+    }
+}
+
+/// Peer message in transit.
+struct Envelope<C: state::Command> {
+    from: usize,
+    to: usize,
+    message: peer::In<C>,
+}
+
+/// Simulated network shared by all servers.
+struct Wire<C: state::Command> {
+    /// ID of the sending server
+    id: usize,
+
+    /// Total number of servers
+    count: usize,
+
+    /// Peer messages in transit
+    wire: Arc<Mutex<Vec<Envelope<C>>>>,
+}
+
+impl<C: state::Command> runtime::Network<C> for Wire<C> {
+    fn connect(&mut self, _: usize, _: internal::Tx<peer::In<C>>) {}
+
+    fn disconnect(&mut self, _: usize) {}
+
+    fn send(&self, id: usize, message: peer::In<C>) {
+        self.wire.lock().push(Envelope {
+            from: self.id,
+            to: id,
+            message,
+        });
+    }
+
+    fn broadcast(&self, message: peer::In<C>) {
+        for id in (0..self.count).filter(|id| *id != self.id) {
+            self.send(id, message.clone());
+        }
+    }
+}
+
+/// Virtual time and pending timers.
+#[derive(Default)]
+struct Timeline {
+    now: time::Duration,
+
+    /// Deadline of each waiting ticker, along with the task to notify
+    timers: BTreeMap<usize, (time::Duration, task::Task)>,
+
+    /// Next unused ticker ID
+    next: usize,
+}
+
+/// Simulated clock shared by all servers.
+#[derive(Clone, Default)]
+struct Virtual(Arc<Mutex<Timeline>>);
+
+impl Virtual {
+    /// Earliest pending timer deadline, if there is one.
+    fn deadline(&self) -> Option<time::Duration> {
+        self.0.lock().timers.values().map(|(deadline, _)| *deadline).min()
+    }
+}
+
+impl runtime::Clock for Virtual {
+    fn interval(&self, delay: time::Duration, period: time::Duration) -> runtime::Interval {
+        let mut timeline = self.0.lock();
+        let id = timeline.next;
+        timeline.next += 1;
+        Box::new(Ticker {
+            id,
+            timeline: self.0.clone(),
+            deadline: timeline.now + delay,
+            period,
+        })
+    }
+}
+
+/// Periodic timer driven by the virtual clock. Each ticker has at most one
+/// pending timer, which is replaced whenever the ticker is polled.
+struct Ticker {
+    id: usize,
+    timeline: Arc<Mutex<Timeline>>,
+    deadline: time::Duration,
+    period: time::Duration,
+}
+
+impl Stream for Ticker {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        let mut timeline = self.timeline.lock();
+        if timeline.now >= self.deadline {
+            timeline.timers.remove(&self.id);
+            self.deadline += self.period;
+            Ok(Async::Ready(Some(())))
+        } else {
+            timeline.timers.insert(self.id, (self.deadline, task::current()));
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.timeline.lock().timers.remove(&self.id);
+    }
+}
+
+/// Seeded source of randomness shared by all servers and the scheduler.
+struct Seeded(Mutex<StdRng>);
+
+impl Seeded {
+    /// Returns a uniformly distributed index in `[0, len)`.
+    fn range(&self, len: usize) -> usize {
+        self.0.lock().gen_range(0, len)
+    }
+
+    /// Returns true with probability `p`.
+    fn chance(&self, p: f64) -> bool {
+        p > 0.0 && self.0.lock().gen_bool(p)
+    }
+}
+
+impl runtime::Random for Seeded {
+    fn random(&self) -> f32 {
+        self.0.lock().gen::<f32>()
+    }
+}
+
+/// Queue of notified task IDs, in notification order.
+#[derive(Default)]
+struct Ready(Mutex<VecDeque<usize>>);
+
+impl executor::Notify for Ready {
+    fn notify(&self, t_id: usize) {
+        let mut ready = self.0.lock();
+        if !ready.contains(&t_id) {
+            ready.push_back(t_id);
+        }
+    }
+}
+
+/// Collects futures spawned while polling a task, along with the server that spawned them.
+struct Spawner {
+    s_id: usize,
+    spawned: Vec<(usize, Box<dyn Future<Item = (), Error = ()> + Send>)>,
+}
+
+impl tokio::executor::Executor for Spawner {
+    fn spawn(
+        &mut self,
+        future: Box<dyn Future<Item = (), Error = ()> + Send>
+    ) -> Result<(), tokio::executor::SpawnError> {
+        self.spawned.push((self.s_id, future));
+        Ok(())
+    }
+}
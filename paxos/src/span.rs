@@ -0,0 +1,197 @@
+//! # Summary
+//!
+//! This module implements structured tracing of protocol messages. Each
+//! scout, commander, acceptor, and replica operation opens a `Span` carrying
+//! the ballot, slot, commander ID, peer ID, and command it concerns, and
+//! every event recorded within the span inherits those fields. Spans are
+//! numbered per server, and a span opened with `Span::child` also records
+//! the ID of the span that caused it, so a replica's proposals and executions
+//! can be traced back to the request or decision that triggered them.
+//!
+//! Events are written as JSON lines to `trace-XX.jsonl` in the storage
+//! directory when enabled with `Config::with_tracing`. Since commands are identified by their client and
+//! local IDs, a single command's path from client request to execution can be
+//! followed by merging the trace files of all servers and filtering on `command`.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic;
+use std::sync::Arc;
+use std::time;
+
+use parking_lot::Mutex;
+use serde_derive::Serialize;
+
+use crate::message;
+use crate::state;
+
+/// Destination for the trace events of a single server.
+pub struct Tracer {
+    /// Unique replica ID
+    id: usize,
+
+    /// Trace file, flushed after every event
+    file: Mutex<std::io::LineWriter<std::fs::File>>,
+
+    /// ID of the next span opened on this server
+    next: atomic::AtomicUsize,
+}
+
+impl Tracer {
+    /// Opens the trace file for server `id` in directory `dir`,
+    /// appending to any existing trace.
+    pub fn new(id: usize, dir: &Path) -> Self {
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(dir.join(format!("trace-{:>02}.jsonl", id)))
+            .expect("[TRACE ERROR]: could not create trace file");
+        Tracer {
+            id,
+            file: Mutex::new(std::io::LineWriter::new(file)),
+            next: atomic::AtomicUsize::new(0),
+        }
+    }
+
+    fn next(&self) -> usize {
+        self.next.fetch_add(1, atomic::Ordering::Relaxed)
+    }
+
+    fn write(&self, span: &Span, event: &'static str) {
+        let time = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let record = Record {
+            time: time.as_secs() * 1_000_000 + time.subsec_micros() as u64,
+            node: self.id,
+            span: span.name,
+            id: span.id,
+            parent: span.parent,
+            event,
+            fields: &span.fields,
+        };
+        let mut file = self.file.lock();
+        if serde_json::to_writer(&mut *file, &record).is_ok() {
+            file.write_all(b"\n").ok();
+        }
+    }
+}
+
+/// Single line of a trace file.
+#[derive(Serialize)]
+struct Record<'a> {
+    /// Time since the Unix epoch (in microseconds)
+    time: u64,
+
+    /// Server that recorded the event
+    node: usize,
+
+    /// Kind of span the event was recorded in
+    span: &'static str,
+
+    /// Span the event was recorded in, unique within its server
+    id: usize,
+
+    /// Span that caused this span, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+
+    /// Name of the event
+    event: &'static str,
+
+    #[serde(flatten)]
+    fields: &'a Fields,
+}
+
+/// Protocol identifiers attached to a span.
+#[derive(Serialize)]
+#[derive(Clone, Debug, Default)]
+struct Fields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ballot: Option<message::Ballot>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slot: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commander: Option<message::CommanderID>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<usize>,
+
+    /// Client and local ID of the command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+}
+
+/// Scope within a protocol thread whose events share the same fields.
+/// Spans without a tracer discard all events.
+#[derive(Clone)]
+pub struct Span {
+    name: &'static str,
+    id: usize,
+    parent: Option<usize>,
+    fields: Fields,
+    tracer: Option<Arc<Tracer>>,
+}
+
+impl Span {
+    pub fn new(name: &'static str, tracer: Option<Arc<Tracer>>) -> Self {
+        Span {
+            name,
+            id: tracer.as_ref().map_or(0, |tracer| tracer.next()),
+            parent: None,
+            fields: Fields::default(),
+            tracer,
+        }
+    }
+
+    /// Opens a new span of kind `name` caused by this one, inheriting its fields.
+    pub fn child(&self, name: &'static str) -> Self {
+        let mut span = Span::new(name, self.tracer.clone());
+        span.parent = Some(self.id);
+        span.fields = self.fields.clone();
+        span
+    }
+
+    pub fn ballot(mut self, ballot: message::Ballot) -> Self {
+        self.fields.ballot = Some(ballot);
+        self
+    }
+
+    pub fn slot(mut self, slot: usize) -> Self {
+        self.fields.slot = Some(slot);
+        self
+    }
+
+    pub fn commander(mut self, commander: message::CommanderID) -> Self {
+        self.fields.commander = Some(commander);
+        self
+    }
+
+    pub fn peer(mut self, peer: usize) -> Self {
+        self.fields.peer = Some(peer);
+        self
+    }
+
+    pub fn command<C: state::Command>(mut self, command: &C) -> Self {
+        if self.tracer.is_some() {
+            self.fields.command = Some(format!("{:?}/{:?}", command.client_id(), command.local_id()));
+        }
+        self
+    }
+
+    /// Records `event` with this span's fields.
+    pub fn event(&self, event: &'static str) {
+        if let Some(tracer) = &self.tracer {
+            tracer.write(self, event);
+        }
+    }
+
+    /// Records `event` with this span's fields, along with peer `id`.
+    pub fn event_with_peer(&self, event: &'static str, id: usize) {
+        if self.tracer.is_some() {
+            self.clone().peer(id).event(event);
+        }
+    }
+}
@@ -55,4 +55,17 @@ pub trait State: Default + Send + 'static {
     type Command: Command;
     type Response: Response;
     fn execute(&mut self, slot: usize, command: Self::Command) -> Option<Self::Response>;
+
+    /// Optional digest of the current state, used to detect replicas that
+    /// diverge because of a non-deterministic `execute`. Replicas with equal
+    /// states must produce equal digests, even across builds and platforms,
+    /// so the digest should not depend on `std::hash::Hash`.
+    fn digest(&self) -> Option<u64> {
+        None
+    }
+
+    /// Identifies this state machine during connection handshakes, so that servers
+    /// and clients built for different state machines refuse to connect. Must stay
+    /// the same across builds and compiler versions, and differ between state machines.
+    fn tag() -> &'static str;
 }
@@ -0,0 +1,39 @@
+//! # Summary
+//!
+//! This module defines a point-in-time summary of a single server, which
+//! operators can request as JSON by enabling `Config::with_admin_port`.
+
+use std::collections::BTreeMap as Map;
+
+use serde_derive::Serialize;
+
+use crate::message;
+
+/// Current state of a single server.
+#[derive(Serialize)]
+#[derive(Clone, Debug)]
+pub struct Status {
+    /// Unique replica ID
+    pub id: usize,
+
+    /// Leader's current ballot
+    pub ballot: message::Ballot,
+
+    /// Whether the leader has been adopted by a majority of acceptors
+    pub active: bool,
+
+    /// Next slot the replica will execute
+    pub decision_slot: usize,
+
+    /// Next slot the replica will propose
+    pub proposal_slot: usize,
+
+    /// IDs of currently connected peers
+    pub peers: Vec<usize>,
+
+    /// Number of currently connected clients
+    pub clients: usize,
+
+    /// Size of each stable storage file (in bytes)
+    pub storage: Map<String, u64>,
+}
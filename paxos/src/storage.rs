@@ -7,12 +7,24 @@
 //! and to deserialize it when recovering a process. This is a naive, inefficient
 //! implementation that clears the file on every write and re-serializes data
 //! from scratch.
+//!
+//! Every file begins with the ID of the cluster that wrote it, so that a server
+//! never recovers from another cluster's storage. Files written before cluster IDs
+//! were introduced are still recognized, and are stamped with the server's cluster
+//! on their next write. An empty file means nothing has been written yet, but any
+//! other file that can't be decoded is an error rather than a fresh start.
 
 use std::io::Seek;
+use std::sync::Arc;
+
+use crate::cluster;
+use crate::metrics;
 
 /// Persistent storage for failure recovery.
 pub struct Storage<S> {
     storage: std::fs::File,
+    cluster: cluster::Cluster,
+    metrics: Option<Arc<metrics::Metrics>>,
     _marker: std::marker::PhantomData<S>,
 }
 
@@ -27,27 +39,118 @@ impl<S> Storage<S> {
             .expect("[STORAGE ERROR]: could not create stable storage");
         Storage {
             storage,
+            cluster: cluster::Cluster::nil(),
+            metrics: None,
             _marker: Default::default(),
         }
     }
+
+    /// Stamps every write with `cluster`, and only loads state written by `cluster`.
+    pub fn with_cluster(mut self, cluster: cluster::Cluster) -> Self {
+        self.cluster = cluster;
+        self
+    }
+
+    /// Records the latency of every write in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<metrics::Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
 }
 
 impl<S: serde::de::DeserializeOwned> Storage<S> {
-    /// Attempts to load state from disk, returning None if nothing
-    /// has been written or an error was encountered during deserialization.
+    /// Attempts to load state from disk, returning None if nothing has been written.
+    /// Will panic if the state can't be decoded, or was written by a different cluster.
     pub fn load(&self) -> Option<S> {
-        bincode::deserialize_from(&self.storage).ok()
+        let len = self.storage.metadata()
+            .expect("[STORAGE ERROR]: could not read stable storage metadata")
+            .len();
+        if len == 0 {
+            return None
+        }
+        let (cluster, state) = decode::<S, _>(&self.storage)
+            .unwrap_or_else(|error| panic!("[STORAGE ERROR]: could not decode stable storage: {}", error));
+        match cluster {
+        | None => warn!("migrating stable storage without a cluster ID to cluster {}", self.cluster),
+        | Some(cluster) if cluster != self.cluster => {
+            panic!("[STORAGE ERROR]: storage belongs to cluster {}, not {}", cluster, self.cluster);
+        }
+        | Some(_) => (),
+        }
+        Some(state)
     }
 }
 
 impl<S: serde::Serialize> Storage<S> {
     /// Saves state to disk. Will panic if modifying the underlying file fails.
     pub fn save(&mut self, state: &S) {
+        let start = std::time::Instant::now();
         self.storage.set_len(0)
             .expect("[STORAGE ERROR]: failed to trim file");
         self.storage.seek(std::io::SeekFrom::Start(0))
             .expect("[STORAGE ERROR]: failed to reset file cursor");
-        bincode::serialize_into(&mut self.storage, state)
+        bincode::serialize_into(&mut self.storage, &(self.cluster, state))
             .expect("[STORAGE ERROR]: failed to serialize state");
+        if let Some(metrics) = &self.metrics {
+            metrics.storage_writes.observe(start.elapsed());
+        }
+    }
+}
+
+/// Decodes the contents of a stable storage file, along with the cluster that wrote it,
+/// which is None if the file predates cluster IDs.
+pub(crate) fn decode<S, R>(mut reader: R) -> bincode::Result<(Option<cluster::Cluster>, S)>
+    where S: serde::de::DeserializeOwned,
+          R: std::io::Read,
+{
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    match exact::<(cluster::Cluster, S)>(&bytes) {
+    | Ok((cluster, state)) => Ok((Some(cluster), state)),
+    | Err(error) => exact::<S>(&bytes)
+        .map(|state| (None, state))
+        .map_err(|_| error),
+    }
+}
+
+/// Deserializes `bytes`, failing unless every byte is consumed, so that one
+/// format is never mistaken for a prefix of the other.
+fn exact<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    let mut cursor = std::io::Cursor::new(bytes);
+    let value = bincode::deserialize_from(&mut cursor)?;
+    let trailing = bytes.len() - cursor.position() as usize;
+    if trailing == 0 {
+        Ok(value)
+    } else {
+        Err(Box::new(bincode::ErrorKind::Custom(format!("{} trailing bytes", trailing))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_files_with_cluster() {
+        let cluster = cluster::Cluster::new_v4();
+        let bytes = bincode::serialize(&(cluster, vec![1u64, 2, 3])).unwrap();
+        let (decoded, state) = decode::<Vec<u64>, _>(&bytes[..]).unwrap();
+        assert_eq!(decoded, Some(cluster));
+        assert_eq!(state, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn decodes_files_without_cluster() {
+        let bytes = bincode::serialize(&vec![1u64, 2, 3]).unwrap();
+        let (decoded, state) = decode::<Vec<u64>, _>(&bytes[..]).unwrap();
+        assert_eq!(decoded, None);
+        assert_eq!(state, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_undecodable_files() {
+        let mut bytes = bincode::serialize(&vec![1u64, 2, 3]).unwrap();
+        bytes.truncate(bytes.len() / 2);
+        assert!(decode::<Vec<u64>, _>(&bytes[..]).is_err());
     }
 }
@@ -47,12 +47,12 @@ pub struct Acceptor<S: state::State> {
 #[serde(bound(serialize = "", deserialize = ""))]
 #[derive(Derivative)]
 #[derivative(Default(bound = ""))]
-struct Stable<S: state::State> {
+pub(crate) struct Stable<S: state::State> {
     /// Highest ballot seen
-    ballot: message::Ballot,
+    pub(crate) ballot: message::Ballot,
 
     /// Most recently accepted PValue per slot
-    accepted: Map<usize, message::PValue<S::Command>>,
+    pub(crate) accepted: Map<usize, message::PValue<S::Command>>,
 }
 
 impl<S: state::State> Future for Acceptor<S> {
@@ -73,8 +73,10 @@ impl<S: state::State> Future for Acceptor<S> {
 impl<S: state::State> Acceptor<S> {
     /// Initializes a new acceptor with the given transmission channels.
     pub fn new(id: usize, rx: internal::Rx<In<S::Command>>, shared_tx: shared::Shared<S>) -> Self {
-        let storage_file = format!("acceptor-{:>02}.paxos", id);
-        let storage = storage::Storage::new(storage_file);
+        let storage_file = shared_tx.read().storage("acceptor");
+        let storage = storage::Storage::new(storage_file)
+            .with_cluster(shared_tx.read().cluster())
+            .with_metrics(shared_tx.read().metrics().clone());
         let stable = storage.load().unwrap_or_default();
         Acceptor {
             id,
@@ -88,6 +90,11 @@ impl<S: state::State> Acceptor<S> {
     /// Updates highest ballot seen, and responds to the sending scout with a P1B.
     /// Only sends PValues for slots that the scout doesn't know decisions for.
     fn respond_p1a(&mut self, p1a: message::P1A) {
+        let span = self.shared_tx.read()
+            .span("acceptor")
+            .ballot(p1a.b_id)
+            .peer(p1a.b_id.l_id);
+        span.event("recv_p1a");
         self.stable.ballot = std::cmp::max(p1a.b_id, self.stable.ballot);
         self.storage.save(&self.stable);
         let pvalues = self.stable.accepted.values()
@@ -100,15 +107,25 @@ impl<S: state::State> Acceptor<S> {
             pvalues,
         });
         trace!("sending {:?} to {}", p1b, p1a.b_id.l_id);
+        span.event("send_p1b");
         self.shared_tx.read().send(p1a.b_id.l_id, p1b)
     }
 
     /// Updates the map of accepted PValues, and responds to the sending commander with a P2B.
     fn respond_p2a(&mut self, c_id: message::CommanderID, pvalue: message::P2A<S::Command>) {
+        let span = self.shared_tx.read()
+            .span("acceptor")
+            .ballot(pvalue.b_id)
+            .slot(pvalue.s_id)
+            .commander(c_id)
+            .command(&*pvalue.command)
+            .peer(pvalue.b_id.l_id);
+        span.event("recv_p2a");
         if pvalue.b_id >= self.stable.ballot {
             self.stable.ballot = pvalue.b_id;
             self.stable.accepted.insert(pvalue.s_id, pvalue.clone());
             self.storage.save(&self.stable);
+            span.event("accepted");
         }
         let p2b = peer::In::P2B(
             c_id,
@@ -118,6 +135,7 @@ impl<S: state::State> Acceptor<S> {
             },
         );
         trace!("sending {:?} to {}", p2b, pvalue.b_id.l_id);
+        span.event("send_p2b");
         self.shared_tx.read().send(pvalue.b_id.l_id, p2b)
     }
 }
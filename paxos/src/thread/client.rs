@@ -3,9 +3,11 @@
 //! This module defines external connections to clients.
 //! Responsible for forwarding messages to and from connected clients.
 
+use std::sync::Arc;
+
 use tokio::prelude::*;
-use tokio::net;
 
+use crate::auth;
 use crate::external;
 use crate::internal;
 use crate::shared;
@@ -13,8 +15,15 @@ use crate::state;
 use crate::state::Command;
 use crate::thread::replica;
 
-/// Represents a client that has not yet sent a message, so we don't know its ID.
+/// Represents a client that has not yet sent a message, so we don't know its ID,
+/// unless it was already authenticated during the connection handshake.
 pub struct Connecting<S: state::State> {
+    /// Client ID established by the connection handshake, if authenticated
+    identity: Option<<S::Command as state::Command>::ClientID>,
+
+    /// Authorization hook for incoming requests
+    auth: Option<Arc<dyn auth::Auth<S::Command>>>,
+
     /// External client receiving channel
     client_rx: Option<external::Rx<S::Command>>,
 
@@ -29,13 +38,19 @@ pub struct Connecting<S: state::State> {
 }
 
 impl<S: state::State> Connecting<S> {
-    pub fn new(
-        stream: net::tcp::TcpStream,
+    pub fn new<I: external::Io>(
+        stream: I,
+        framing: external::Framing,
         replica_tx: internal::Tx<replica::In<S::Command>>,
         shared_tx: shared::Shared<S>,
+        identity: Option<<S::Command as state::Command>::ClientID>,
+        auth: Option<Arc<dyn auth::Auth<S::Command>>>,
     ) -> Self {
-        let (client_rx, client_tx) = external::new(stream);
+        let (client_rx, client_tx) = external::with_framing(stream, framing);
+        let client_rx = client_rx.with_metrics(shared_tx.read().metrics().clone());
         Connecting {
+            identity,
+            auth,
             client_rx: Some(client_rx),
             client_tx: Some(client_tx),
             replica_tx: Some(replica_tx),
@@ -48,30 +63,30 @@ impl<S: state::State> Future for Connecting<S> {
     type Item = Client<S>;
     type Error = ();
     fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
-        while let Async::Ready(Some(message)) = self.client_rx.as_mut().unwrap().poll()?  {
+        while let Async::Ready(Some(message)) = self.client_rx.as_mut().unwrap().poll().map_err(disconnect)?  {
             // After we receive a message, we can read off the client's ID,
             // register it with the shared transmission hub, and
             // promote it to a Client struct. Safe to unwrap here because
             // we always initialize with Some, and always return after moving
             // out of the option.
-            info!("connected to {:?}", message.client_id());
-            let client_id = message.client_id();
+            let client_id = self.identity.take().unwrap_or_else(|| message.client_id());
+            info!("connected to {:?}", client_id);
             let (rx, tx) = internal::new();
             self.shared_tx.as_mut()
                 .unwrap()
                 .write()
                 .connect_client(client_id.clone(), tx);
-            self.replica_tx.as_mut()
-                .unwrap()
-                .send(replica::In::Request(message));
-            return Ok(Async::Ready(Client {
+            let client = Client {
                 client_id,
+                auth: self.auth.take(),
                 client_rx: self.client_rx.take().unwrap(),
                 client_tx: self.client_tx.take().unwrap(),
                 replica_tx: self.replica_tx.take().unwrap(),
                 shared_tx: self.shared_tx.take().unwrap(),
                 rx,
-            }))
+            };
+            client.respond_incoming(message)?;
+            return Ok(Async::Ready(client))
         }
         Ok(Async::NotReady)
     }
@@ -86,6 +101,9 @@ pub struct Client<S: state::State> {
     /// Client ID
     client_id: <S::Command as state::Command>::ClientID,
 
+    /// Authorization hook for incoming requests
+    auth: Option<Arc<dyn auth::Auth<S::Command>>>,
+
     /// External client receiving channel
     client_rx: external::Rx<S::Command>,
 
@@ -99,25 +117,47 @@ pub struct Client<S: state::State> {
     shared_tx: shared::Shared<S>,
 }
 
+impl<S: state::State> Client<S> {
+    /// Forward an incoming request to the replica if it is authorized. Commands
+    /// issued under any other client ID, or that fail authorization, end the
+    /// connection, so that clients waiting for a response don't hang.
+    fn respond_incoming(&self, message: S::Command) -> Result<(), ()> {
+        if message.client_id() != self.client_id {
+            warn!("{:?} sent command as {:?}", self.client_id, message.client_id());
+            return Err(())
+        }
+        match &self.auth {
+        | Some(auth) if !auth.authorize(&self.client_id, &message) => {
+            warn!("{:?} is not authorized to send {:?}", self.client_id, message);
+            Err(())
+        }
+        | _ => {
+            self.replica_tx.send(replica::In::Request(message));
+            Ok(())
+        }
+        }
+    }
+}
+
 impl<S: state::State> Future for Client<S> {
     type Item = ();
     type Error = ();
     fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
 
         // Forward incoming requests
-        while let Async::Ready(Some(message)) = self.client_rx.poll()?  {
+        while let Async::Ready(Some(message)) = self.client_rx.poll().map_err(disconnect)?  {
             trace!("received {:?}", message);
-            self.replica_tx.send(replica::In::Request(message));
+            self.respond_incoming(message)?;
         }
 
         // Forward outgoing responses
         while let Async::Ready(Some(message)) = self.rx.poll()?  {
             trace!("sending {:?}", message);
-            self.client_tx.start_send(message)?;
+            self.client_tx.start_send(message).map_err(disconnect)?;
         }
 
         // Complete sends
-        if let Async::NotReady = self.client_tx.poll_complete()? {
+        if let Async::NotReady = self.client_tx.poll_complete().map_err(disconnect)? {
             return Ok(Async::NotReady)
         }
 
@@ -125,6 +165,11 @@ impl<S: state::State> Future for Client<S> {
     }
 }
 
+/// Log the reason for closing a client connection.
+fn disconnect(error: external::Error) {
+    warn!("closing client connection: {}", error);
+}
+
 impl<S: state::State> Drop for Client<S> {
     fn drop(&mut self) {
         info!("disconnected from {:?}", self.client_id);
@@ -3,14 +3,15 @@
 //! This module defines the `Commander` struct, which is responsible
 //! for proposing specific slot-command mappings to acceptors.
 
-use std::collections::HashSet as Set;
+use std::collections::BTreeSet as Set;
 
 use tokio::prelude::*;
-use tokio::timer;
 
 use crate::internal;
 use crate::message;
+use crate::runtime;
 use crate::shared;
+use crate::span;
 use crate::state;
 use crate::thread::{leader, peer};
 
@@ -38,10 +39,13 @@ pub struct Commander<S: state::State> {
     pvalue: message::PValue<S::Command>,
 
     /// Interval at which to re-send P2A messages to unresponsive acceptors
-    timeout: timer::Interval,
+    timeout: runtime::Interval,
 
     /// Acceptors that have yet to respond
     waiting: Set<usize>,
+
+    /// Trace span for this ballot and slot
+    span: span::Span,
 }
 
 impl<S: state::State> Commander<S> {
@@ -59,11 +63,15 @@ impl<S: state::State> Commander<S> {
             b_id: pvalue.b_id,
             s_id: pvalue.s_id,
         };
-        let timeout = timer::Interval::new(
-            std::time::Instant::now() + timeout,
-            timeout,
-        );
+        let timeout = shared_tx.read().interval(timeout, timeout);
         debug!("starting for {:?}", id);
+        let span = shared_tx.read()
+            .span("commander")
+            .ballot(pvalue.b_id)
+            .slot(pvalue.s_id)
+            .commander(id)
+            .command(&*pvalue.command);
+        span.event("open");
         shared_tx.write().connect_commander(id, tx);
         let commander = Commander {
             id,
@@ -74,6 +82,7 @@ impl<S: state::State> Commander<S> {
             minority,
             pvalue,
             timeout,
+            span,
         };
         commander.send_p2a();
         commander
@@ -85,6 +94,9 @@ impl<S: state::State> Commander<S> {
             self.id,
             self.pvalue.clone()
         );
+        for id in &self.waiting {
+            self.span.event_with_peer("send_p2a", *id);
+        }
         self.shared_tx
             .read()
             .narrowcast(&self.waiting, p2a);
@@ -93,6 +105,7 @@ impl<S: state::State> Commander<S> {
     /// Broadcast decisions to all replicas
     fn send_decide(&self) {
         debug!("{:?} decided", self.pvalue);
+        self.span.event("decided");
         let decide = message::Proposal {
             s_id: self.pvalue.s_id,
             command: self.pvalue.command.clone(),
@@ -105,6 +118,7 @@ impl<S: state::State> Commander<S> {
     /// Notify leader that its ballot has been preempted
     fn send_preempt(&self, b_id: message::Ballot) {
         debug!("{:?} preempted", self.pvalue);
+        self.span.event("preempted");
         let preempt = leader::In::Preempt::<S::Command>(b_id);
         self.leader_tx.send(preempt);
     }
@@ -116,13 +130,14 @@ impl<S: state::State> Future for Commander<S> {
     fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
 
         // Narrowcast P2A to acceptors who haven't responded
-        while let Async::Ready(Some(_)) = self.timeout.poll().map_err(|_| ())?  {
+        while let Async::Ready(Some(_)) = self.timeout.poll()?  {
             self.send_p2a();
         }
 
         // Respond to incoming P2B messages
         while let Async::Ready(Some(p2b)) = self.rx.poll()? {
             debug!("received {:?}", p2b);
+            self.span.event_with_peer("recv_p2b", p2b.a_id);
 
             // Commander has not been preempted
             if p2b.b_id == self.pvalue.b_id {
@@ -150,6 +165,7 @@ impl<S: state::State> Future for Commander<S> {
 impl<S: state::State> Drop for Commander<S> {
     fn drop(&mut self) {
         debug!("dropping {:?}", self.pvalue);
+        self.span.event("close");
         self.shared_tx.write().disconnect_commander(self.id);
     }
 }
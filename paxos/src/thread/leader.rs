@@ -4,7 +4,7 @@
 //! for vying for a majority of acceptors with a scout, and then 
 //! proposing commands to them via commanders.
 
-use std::collections::HashMap as Map;
+use std::collections::BTreeMap as Map;
 use std::time;
 
 use serde_derive::{Serialize, Deserialize};
@@ -65,15 +65,15 @@ pub struct Leader<S: state::State> {
 /// proposals they plan to propose to acceptors.
 #[derive(Serialize, Deserialize)]
 #[serde(bound(serialize = "", deserialize = ""))]
-struct Stable<S: state::State> {
+pub(crate) struct Stable<S: state::State> {
     /// Leader's current ballot
-    ballot: message::Ballot,
+    pub(crate) ballot: message::Ballot,
 
     /// Planned proposals
-    proposals: Map<usize, message::Command<S::Command>>,
+    pub(crate) proposals: Map<usize, message::Command<S::Command>>,
 
     /// Latest known decision
-    decided: Option<usize>,
+    pub(crate) decided: Option<usize>,
 }
 
 impl<S: state::State> Leader<S> {
@@ -86,14 +86,17 @@ impl<S: state::State> Leader<S> {
         shared_tx: shared::Shared<S>,
         timeout: time::Duration,
     ) -> Self {
-        let storage_file = format!("leader-{:>02}.paxos", id);
-        let storage = storage::Storage::new(storage_file);
+        let storage_file = shared_tx.read().storage("leader");
+        let storage = storage::Storage::new(storage_file)
+            .with_cluster(shared_tx.read().cluster())
+            .with_metrics(shared_tx.read().metrics().clone());
         let stable = storage.load()
             .unwrap_or(Stable {
                 ballot: message::Ballot { b_id: 1, l_id: id }, 
                 proposals: Map::default(), 
                 decided: None,
             });
+        let backoff = 100.0 * shared_tx.read().random();
         let leader = Leader {
             id,
             count,
@@ -101,11 +104,12 @@ impl<S: state::State> Leader<S> {
             tx,
             shared_tx,
             active: false,
-            backoff: 100.0 * rand::random::<f32>(),
+            backoff,
             storage,
             stable,
             timeout,
         };
+        leader.update_metrics();
         leader.spawn_scout();
         leader
     }
@@ -128,13 +132,15 @@ impl<S: state::State> Leader<S> {
     fn respond_preempt(&mut self, ballot: message::Ballot) {
         if ballot <= self.stable.ballot { return }
         debug!("preempted by {:?}", ballot);
+        self.shared_tx.read().metrics().preemptions.inc();
         self.active = false;
         self.stable.ballot = message::Ballot {
             b_id: ballot.b_id + 1,
             l_id: self.id,
         };
         self.storage.save(&self.stable);
-        self.backoff *= 1.0 + rand::random::<f32>() / 2.0;
+        self.update_metrics();
+        self.backoff *= 1.0 + self.shared_tx.read().random() / 2.0;
         self.spawn_scout();
     }
 
@@ -167,7 +173,9 @@ impl<S: state::State> Leader<S> {
         }
 
         info!("adopted with ballot {:?}", self.stable.ballot);
+        self.shared_tx.read().metrics().ballots_adopted.inc();
         self.active = true;
+        self.update_metrics();
     }
 
     /// Mark a decided slot to reduce P1B message size and avoid spawning
@@ -214,9 +222,19 @@ impl<S: state::State> Leader<S> {
             self.count,
             self.timeout,
         );
+        self.shared_tx.read().metrics().commanders_spawned.inc();
         tokio::spawn(commander);
     }
 
+    /// Publish the current ballot and whether it has been adopted.
+    fn update_metrics(&self) {
+        let shared = self.shared_tx.read();
+        let metrics = shared.metrics();
+        metrics.ballot.set(self.stable.ballot.b_id);
+        metrics.ballot_leader.set(self.stable.ballot.l_id);
+        metrics.leader_active.set(self.active as usize);
+    }
+
     /// Spawn a new scout thread for the current ballot.
     fn spawn_scout(&self) {
         let scout = scout::Scout::new(
@@ -5,19 +5,19 @@
 
 use serde_derive::{Serialize, Deserialize};
 use tokio::prelude::*;
-use tokio::net;
 
 use crate::external;
 use crate::internal;
 use crate::message;
+use crate::runtime;
 use crate::shared::Shared;
 use crate::state;
 use crate::thread::acceptor;
 
 /// Peer servers can receive messages between
 /// scouts, commanders, and acceptors, decisions
-/// from commanders, and pings to detect failed
-/// servers.
+/// from commanders, state digests from replicas,
+/// and pings to detect failed servers.
 #[derive(Debug, Derivative, Deserialize, Serialize)]
 #[derivative(Clone(bound = ""))]
 #[serde(bound(serialize = "", deserialize = ""))]
@@ -27,87 +27,12 @@ pub enum In<C: state::Command> {
     P2A(message::CommanderID, message::P2A<C>),
     P2B(message::CommanderID, message::P2B),
     Decision(message::Proposal<C>),
+    Digest(message::Digest),
     Ping(usize),
 }
 
-/// Represents a peer that has not yet sent a ping, so we don't know its ID.
-pub struct Connecting<S: state::State> {
-    /// ID of the current server (not the peer)
-    self_id: usize,
-
-    /// External peer receiving channel
-    peer_rx: Option<external::Rx<In<S::Command>>>,
-
-    /// External peer transmitting channel
-    peer_tx: Option<external::Tx<In<S::Command>>>,
-
-    /// Internal acceptor transmitting channel
-    acceptor_tx: Option<internal::Tx<acceptor::In<S::Command>>>,
-
-    /// Internal shared transmitting channels
-    shared_tx: Option<Shared<S>>,
-
-    /// Ping interval for detecting failed connections
-    timeout: std::time::Duration,
-}
-
-impl<S: state::State> Connecting<S> {
-    pub fn new(
-        self_id: usize,
-        stream: net::tcp::TcpStream,
-        acceptor_tx: internal::Tx<acceptor::In<S::Command>>,
-        shared_tx: Shared<S>,
-        timeout: std::time::Duration,
-    ) -> Self {
-        let (peer_rx, peer_tx) = external::new(stream);
-        Connecting {
-            self_id,
-            peer_rx: Some(peer_rx),
-            peer_tx: Some(peer_tx),
-            acceptor_tx: Some(acceptor_tx),
-            shared_tx: Some(shared_tx),
-            timeout,
-        }
-    }
-}
-
-impl<S: state::State> Future for Connecting<S> {
-    type Item = Peer<S>;
-    type Error = ();
-    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
-        while let Async::Ready(Some(message)) = self.peer_rx.as_mut().unwrap().poll()?  {
-            match message {
-            | In::Ping(peer_id) => {
-                // After we receiving a ping, we can read off the connected server's ID,
-                // register it with the shared transmission hub, and promote it to a
-                // Peer struct. Safe to unwrap here because we always initialize with Some
-                // and always return after moving out of the option.
-                info!("connected to {}", peer_id);
-                let (rx, tx) = internal::new();
-                self.shared_tx.as_mut()
-                    .unwrap()
-                    .write()
-                    .connect_peer(peer_id, tx);
-                return Ok(Async::Ready(Peer {
-                    self_id: self.self_id,
-                    peer_id,
-                    rx,
-                    peer_rx: self.peer_rx.take().unwrap(),
-                    peer_tx: self.peer_tx.take().unwrap(),
-                    acceptor_tx: self.acceptor_tx.take().unwrap(),
-                    shared_tx: self.shared_tx.take().unwrap(),
-                    timeout: tokio::timer::Interval::new_interval(self.timeout),
-                }))
-            }
-            | _ => (),
-            }
-        }
-        Ok(Async::NotReady)
-    }
-}
-
-/// Represents a peer server with known ID that is registered
-/// with the shared transmission hub.
+/// Represents a peer server whose ID was established by the connection
+/// handshake, and that is registered with the shared transmission hub.
 pub struct Peer<S: state::State> {
     /// ID of connected server
     peer_id: usize,
@@ -131,21 +56,30 @@ pub struct Peer<S: state::State> {
     shared_tx: Shared<S>,
 
     /// Ping interval for detecting failed connections
-    timeout: tokio::timer::Interval,
+    timeout: runtime::Interval,
+
+    /// Outgoing messages held back by an injected delay, in the order they were sent
+    delayed: Vec<(runtime::Delay, In<S::Command>)>,
 }
 
 impl<S: state::State> Peer<S> {
-    pub fn new(
+    pub fn new<I: external::Io>(
         self_id: usize,
         peer_id: usize,
-        stream: net::tcp::TcpStream,
+        stream: I,
+        framing: external::Framing,
         acceptor_tx: internal::Tx<acceptor::In<S::Command>>,
         shared_tx: Shared<S>,
         timeout: std::time::Duration,
     ) -> Self {
-        let (peer_rx, peer_tx) = external::new(stream);
+        let (peer_rx, peer_tx) = external::with_framing(stream, framing);
+        let peer_rx = peer_rx.with_metrics(shared_tx.read().metrics().clone());
         let (rx, tx) = internal::new();
-        shared_tx.write().connect_peer(peer_id, tx);
+        let timeout = {
+            let mut shared_tx = shared_tx.write();
+            shared_tx.connect_peer(peer_id, tx);
+            shared_tx.interval(timeout, timeout)
+        };
         info!("connected to {}", peer_id);
         Peer {
             self_id,
@@ -154,8 +88,9 @@ impl<S: state::State> Peer<S> {
             peer_tx,
             acceptor_tx,
             shared_tx,
-            timeout: tokio::timer::Interval::new_interval(timeout),
+            timeout,
             rx,
+            delayed: Vec::new(),
         }
     }
 }
@@ -169,6 +104,25 @@ impl<S: state::State> Peer<S> {
         | message            => self.shared_tx.read().forward(message),
         }
     }
+
+    /// Send outgoing messages to the connected server, subject to any injected faults.
+    fn respond_outgoing(&mut self, message: In<S::Command>) -> Result<(), ()> {
+        let (link, dropped) = {
+            let shared_tx = self.shared_tx.read();
+            let link = shared_tx.link(self.peer_id);
+            (link, link.drop_rate > 0.0 && (shared_tx.random() as f64) < link.drop_rate)
+        };
+        if link.blocked || dropped {
+            trace!("dropping {:?}", message);
+        } else if link.delay > std::time::Duration::default() {
+            trace!("delaying {:?} by {:?}", message, link.delay);
+            let delay = self.shared_tx.read().delay(link.delay);
+            self.delayed.push((delay, message));
+        } else {
+            self.peer_tx.start_send(message).map_err(disconnect)?;
+        }
+        Ok(())
+    }
 }
 
 impl<S: state::State> Future for Peer<S> {
@@ -177,12 +131,12 @@ impl<S: state::State> Future for Peer<S> {
     fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
 
         // Drop connections to unresponsive peers
-        while let Async::Ready(Some(_)) = self.timeout.poll().map_err(|_| ())?  {
-            self.peer_tx.start_send(In::Ping(self.self_id))?;
+        while let Async::Ready(Some(_)) = self.timeout.poll()?  {
+            self.respond_outgoing(In::Ping(self.self_id))?;
         }
 
         // Forward incoming messages
-        while let Async::Ready(Some(message)) = self.peer_rx.poll()?  {
+        while let Async::Ready(Some(message)) = self.peer_rx.poll().map_err(disconnect)?  {
             if let In::Ping(_) = &message {} else {
                 trace!("received {:?}", message);
                 self.respond_incoming(message);
@@ -192,11 +146,22 @@ impl<S: state::State> Future for Peer<S> {
         // Forward outgoing messages
         while let Async::Ready(Some(message)) = self.rx.poll()? {
             trace!("sending {:?}", message);
-            self.peer_tx.start_send(message)?;
+            self.respond_outgoing(message)?;
+        }
+
+        // Forward outgoing messages whose injected delay has elapsed
+        let mut index = 0;
+        while index < self.delayed.len() {
+            if let Async::Ready(()) = self.delayed[index].0.poll()? {
+                let (_, expired) = self.delayed.remove(index);
+                self.peer_tx.start_send(expired).map_err(disconnect)?;
+            } else {
+                index += 1;
+            }
         }
 
         // Complete sends
-        if let Async::NotReady = self.peer_tx.poll_complete()? {
+        if let Async::NotReady = self.peer_tx.poll_complete().map_err(disconnect)? {
             return Ok(Async::NotReady)
         }
 
@@ -204,6 +169,11 @@ impl<S: state::State> Future for Peer<S> {
     }
 }
 
+/// Log the reason for closing a peer connection.
+fn disconnect(error: external::Error) {
+    warn!("closing peer connection: {}", error);
+}
+
 impl<S: state::State> Drop for Peer<S> {
     fn drop(&mut self) {
         info!("disconnected from {}", self.peer_id);
@@ -4,7 +4,9 @@
 //! for communicating with the client and executing decisions on
 //! the state machine.
 
+use std::collections::BTreeMap;
 use std::collections::HashMap as Map;
+use std::collections::HashSet as Set;
 
 use serde_derive::{Serialize, Deserialize};
 use tokio::prelude::*;
@@ -12,22 +14,33 @@ use tokio::prelude::*;
 use crate::internal;
 use crate::message;
 use crate::shared;
+use crate::span;
 use crate::state;
 use crate::state::Command;
 use crate::storage;
-use crate::thread::leader;
+use crate::thread::{leader, peer};
+
+/// Number of slots between state digests
+const DIGEST_PERIOD: usize = 64;
+
+/// Number of this replica's most recent digests to keep for comparison
+const DIGEST_HISTORY: usize = 16;
 
 /// Replicas can only receive requests from the client,
-/// or decisions from commanders.
+/// decisions from commanders, or digests from other replicas.
 #[derive(Debug)]
 pub enum In<C: state::Command> {
     Request(C),
     Decision(message::Proposal<C>),
+    Digest(message::Digest),
 }
 
 /// Replicas maintain the actual state machine, and communicate
 /// with the client.
 pub struct Replica<S: state::State> {
+    /// Unique ID of replica
+    id: usize,
+
     /// Internal receiving channel
     rx: internal::Rx<In<S::Command>>,
 
@@ -45,24 +58,50 @@ pub struct Replica<S: state::State> {
 
     /// User-provided state machine
     state: S,
+
+    /// One past the highest slot decided so far
+    decided: usize,
+
+    /// Recent digests of this replica's state, by slot
+    digests: BTreeMap<usize, u64>,
+
+    /// Digests from other replicas for slots this replica has yet to execute
+    pending: BTreeMap<usize, Vec<message::Digest>>,
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(bound(serialize = "", deserialize = ""))]
 #[derive(Derivative)]
 #[derivative(Default(bound = ""))]
-struct Stable<S: state::State> {
+pub(crate) struct Stable<S: state::State> {
     /// Slot for next proposal
-    proposal_slot: usize,
+    pub(crate) proposal_slot: usize,
 
     /// Slot for next decision
-    decision_slot: usize,
+    pub(crate) decision_slot: usize,
 
     /// Map of latest proposals for each slot
-    proposals: Map<usize, message::Command<S::Command>>,
+    pub(crate) proposals: Map<usize, message::Command<S::Command>>,
 
     /// Map of decisions for each slot
-    decisions: Map<usize, message::Command<S::Command>>,
+    pub(crate) decisions: Map<usize, message::Command<S::Command>>,
+}
+
+/// Rebuild a state machine by executing `commands`, decided in consecutive slots
+/// starting from zero, and skipping any command already decided in an earlier
+/// slot, exactly as `Replica::perform` does.
+pub(crate) fn rebuild<S, I>(commands: I) -> S
+    where S: state::State,
+          I: IntoIterator<Item = message::Command<S::Command>>,
+{
+    let mut state = S::default();
+    let mut executed = Set::new();
+    for (slot, command) in commands.into_iter().enumerate() {
+        if executed.insert(command.clone()) {
+            state.execute(slot, command.inner());
+        }
+    }
+    state
 }
 
 impl<S: state::State> Replica<S> {
@@ -72,47 +111,66 @@ impl<S: state::State> Replica<S> {
         shared_tx: shared::Shared<S>,
         rx: internal::Rx<In<S::Command>>,
     ) -> Self {
-        let storage_file = format!("replica-{:>02}.paxos", id);
-        let storage: storage::Storage<Stable<S>> = storage::Storage::new(storage_file);
+        let storage_file = shared_tx.read().storage("replica");
+        let storage: storage::Storage<Stable<S>> = storage::Storage::new(storage_file)
+            .with_cluster(shared_tx.read().cluster())
+            .with_metrics(shared_tx.read().metrics().clone());
         let stable = storage.load().unwrap_or_default();
-        let mut state = S::default();
+        let decided = stable.decisions.keys().max().map_or(0, |slot| slot + 1);
 
         // Replay decisions in order
-        for slot in 0..stable.decision_slot {
-            state.execute(slot, stable.decisions[&slot].clone().inner());
-        }
+        let state = rebuild::<S, _>((0..stable.decision_slot).map(|slot| stable.decisions[&slot].clone()));
 
-        Replica {
+        let replica = Replica {
+            id,
             leader_tx,
             shared_tx,
             rx,
             stable,
             storage,
             state,
-        }
+            decided,
+            digests: BTreeMap::default(),
+            pending: BTreeMap::default(),
+        };
+        replica.update_metrics();
+        replica
     }
 
     /// Propose the provided command.
     fn respond_request(&mut self, command: S::Command) {
-        self.propose(command.into());
+        let span = self.shared_tx.read()
+            .span("replica")
+            .command(&command);
+        span.event("request");
+        self.propose(command.into(), &span);
     }
 
     /// Execute the provided decision, re-proposing any invalidated proposals.
     fn respond_decision(&mut self, decision: message::Proposal<S::Command>) {
+        let span = self.shared_tx.read()
+            .span("replica")
+            .slot(decision.s_id)
+            .command(&*decision.command);
+        span.event("decision");
+        self.decided = self.decided.max(decision.s_id + 1);
         self.stable.decisions.insert(decision.s_id, decision.command);
         self.storage.save(&self.stable);
         while let Some(c1) = self.stable.decisions.get(&self.stable.decision_slot).cloned() {
             if let Some(c2) = self.stable.proposals.get(&self.stable.decision_slot) {
                 if c1 != *c2 {
-                    self.propose(c2.clone());
+                    self.propose(c2.clone(), &span);
                 }
             }
-            self.perform(c1);
+            self.perform(c1, &span);
+            self.publish_digest();
         }
+        self.update_metrics();
     }
 
-    /// Propose the provided command by delegating to the leader.
-    fn propose(&mut self, command: message::Command<S::Command>) {
+    /// Propose the provided command by delegating to the leader,
+    /// tracing the proposal as a child of span `parent`.
+    fn propose(&mut self, command: message::Command<S::Command>, parent: &span::Span) {
         for previous in self.stable.decisions.values() {
             if *previous == command { return }
         }
@@ -123,6 +181,10 @@ impl<S: state::State> Replica<S> {
         }
 
         info!("proposing {:?} for slot {:?}", command, self.stable.proposal_slot);
+        parent.child("replica")
+            .slot(self.stable.proposal_slot)
+            .command(&*command)
+            .event("propose");
         self.stable.proposals.insert(self.stable.proposal_slot, command.clone());
         self.storage.save(&self.stable);
 
@@ -132,11 +194,14 @@ impl<S: state::State> Replica<S> {
         });
 
         self.leader_tx.send(proposal);
+        self.update_metrics();
     }
 
     /// Perform the provided command by executing it on the state machine, sending
-    /// a response back to the client if there was one.
-    fn perform(&mut self, command: message::Command<S::Command>) {
+    /// a response back to the client if there was one, and tracing the execution
+    /// as a child of span `parent`.
+    fn perform(&mut self, command: message::Command<S::Command>, parent: &span::Span) {
+        self.shared_tx.read().metrics().slots_decided.inc();
         for (s, previous) in &self.stable.decisions {
             if *previous == command && *s < self.stable.decision_slot {
                 self.stable.decision_slot += 1;
@@ -145,6 +210,10 @@ impl<S: state::State> Replica<S> {
             }
         }
         info!("executing {:?} in slot {}", command, self.stable.decision_slot);
+        parent.child("replica")
+            .slot(self.stable.decision_slot)
+            .command(&*command)
+            .event("execute");
         let client_id = command.client_id();
         if let Some(result) = self.state.execute(self.stable.decision_slot, command.inner()) {
             self.shared_tx
@@ -159,6 +228,71 @@ impl<S: state::State> Replica<S> {
         self.storage.save(&self.stable);
         self.leader_tx.send(decide);
     }
+
+    /// Broadcast a digest of the state machine every `DIGEST_PERIOD` slots,
+    /// and check it against any digests already received for the same slot.
+    fn publish_digest(&mut self) {
+        let slot = self.stable.decision_slot;
+        if slot % DIGEST_PERIOD != 0 { return }
+        let digest = match self.state.digest() {
+        | Some(digest) => digest,
+        | None => return,
+        };
+
+        self.digests.insert(slot, digest);
+        while self.digests.len() > DIGEST_HISTORY {
+            let oldest = *self.digests.keys().next().unwrap();
+            self.digests.remove(&oldest);
+        }
+
+        for remote in self.pending.remove(&slot).unwrap_or_default() {
+            self.compare(remote, digest);
+        }
+        self.pending = self.pending.split_off(&slot);
+
+        self.shared_tx
+            .read()
+            .broadcast(peer::In::Digest(message::Digest { r_id: self.id, s_id: slot, digest }));
+    }
+
+    /// Compare a digest from another replica against this replica's digest for
+    /// the same slot, or hold onto it until this replica reaches that slot.
+    fn respond_digest(&mut self, remote: message::Digest) {
+        if remote.r_id == self.id { return }
+        if let Some(digest) = self.digests.get(&remote.s_id).cloned() {
+            self.compare(remote, digest);
+        } else if remote.s_id > self.stable.decision_slot {
+            self.pending.entry(remote.s_id).or_default().push(remote);
+        }
+    }
+
+    /// Raise an alarm if `remote` disagrees with this replica's `digest`.
+    fn compare(&self, remote: message::Digest, digest: u64) {
+        if remote.digest == digest { return }
+        error!(
+            "diverged from replica {} at slot {}: digest {:x} != {:x}",
+            remote.r_id,
+            remote.s_id,
+            digest,
+            remote.digest,
+        );
+        let shared = self.shared_tx.read();
+        shared.metrics().divergences.inc();
+        shared.span("replica")
+            .slot(remote.s_id)
+            .peer(remote.r_id)
+            .event("diverged");
+    }
+
+    /// Update gauges describing outstanding proposals and decisions.
+    fn update_metrics(&self) {
+        let shared = self.shared_tx.read();
+        let metrics = shared.metrics();
+        metrics.decision_lag.set(self.decided.saturating_sub(self.stable.decision_slot));
+        metrics.client_queue_depth.set(self.stable.proposals.len());
+        metrics.decision_slot.set(self.stable.decision_slot);
+        metrics.proposal_slot.set(self.stable.proposal_slot);
+    }
 }
 
 impl<S: state::State> Future for Replica<S> {
@@ -170,6 +304,7 @@ impl<S: state::State> Future for Replica<S> {
             match message {
             | In::Request(command) => self.respond_request(command),
             | In::Decision(proposal) => self.respond_decision(proposal),
+            | In::Digest(digest) => self.respond_digest(digest),
             }
         }
         Ok(Async::NotReady)
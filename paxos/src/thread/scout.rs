@@ -9,11 +9,12 @@ use std::collections::HashSet as Set;
 use std::time;
 
 use tokio::prelude::*;
-use tokio::timer;
 
 use crate::internal;
 use crate::message;
+use crate::runtime;
 use crate::shared;
+use crate::span;
 use crate::state;
 use crate::thread::{leader, peer};
 
@@ -45,10 +46,13 @@ pub struct Scout<S: state::State> {
     pvalues: Set<message::PValue<S::Command>>,
 
     /// Interval at which to re-send P1A messages to unresponsive acceptors
-    timeout: timer::Interval,
+    timeout: runtime::Interval,
 
     /// Acceptors that have yet to respond
     waiting: Set<usize>,
+
+    /// Trace span for this ballot
+    span: span::Span,
 }
 
 impl<S: state::State> Scout<S> {
@@ -63,14 +67,13 @@ impl<S: state::State> Scout<S> {
     ) -> Self {
         let waiting = (0..count).collect();
         let minority = (count - 1) / 2;
-        let timeout = timer::Interval::new(
-            time::Instant::now() + delay,
-            timeout,
-        );
+        let timeout = shared_tx.read().interval(delay, timeout);
         let pvalues = Set::default();
         let (rx, tx) = internal::new();
         shared_tx.write().replace_scout(tx);
         debug!("starting for {:?} with delay {:?}", ballot, delay);
+        let span = shared_tx.read().span("scout").ballot(ballot);
+        span.event("open");
         Scout {
             rx,
             leader_tx,
@@ -81,6 +84,7 @@ impl<S: state::State> Scout<S> {
             pvalues,
             timeout,
             waiting,
+            span,
         }
     }
 
@@ -90,14 +94,21 @@ impl<S: state::State> Scout<S> {
             b_id: self.ballot,
             decided: self.decided,
         });
+        // Send in ID order so that simulated executions are reproducible
+        let mut waiting = self.waiting.iter().collect::<Vec<_>>();
+        waiting.sort();
+        for id in &waiting {
+            self.span.event_with_peer("send_p1a", **id);
+        }
         self.shared_tx
             .read()
-            .narrowcast(&self.waiting, p1a);
+            .narrowcast(waiting, p1a);
     }
 
     /// Inform leader that its ballot has been adopted by a majority of acceptors
     fn send_adopt(&mut self) {
         debug!("{:?} adopted", self.ballot);
+        self.span.event("adopted");
         let pvalues = std::mem::replace(&mut self.pvalues, Set::with_capacity(0))
             .into_iter()
             .collect();
@@ -108,6 +119,7 @@ impl<S: state::State> Scout<S> {
     /// Notify leader that its ballot has been preempted
     fn send_preempt(&self, b_id: message::Ballot) {
         debug!("{:?} preempted by {:?}", self.ballot, b_id);
+        self.span.event("preempted");
         let preempt = leader::In::Preempt::<S::Command>(b_id);
         self.leader_tx.send(preempt);
     }
@@ -118,12 +130,13 @@ impl<S: state::State> Future for Scout<S> {
     type Error = ();
     fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
         // Narrowcast P1A to acceptors who haven't responded
-        while let Async::Ready(Some(_)) = self.timeout.poll().map_err(|_| ())? {
+        while let Async::Ready(Some(_)) = self.timeout.poll()? {
             self.send_p1a();
         }
 
         // Respond to incoming P1B messages
         while let Async::Ready(Some(p1b)) = self.rx.poll()? {
+            self.span.event_with_peer("recv_p1b", p1b.a_id);
 
             // Scout has not been preempted
             if p1b.b_id == self.ballot {
@@ -152,5 +165,6 @@ impl<S: state::State> Future for Scout<S> {
 impl<S: state::State> Drop for Scout<S> {
     fn drop(&mut self) {
         debug!("dropping {:?}", self.ballot); 
+        self.span.event("close");
     }
 }
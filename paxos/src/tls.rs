@@ -0,0 +1,153 @@
+//! # Summary
+//!
+//! This module configures optional TLS encryption for peer and client
+//! connections using `rustls`. Each server presents the PEM-encoded certificate
+//! chain and private key it was configured with, and verifies the servers it
+//! connects to against a PEM-encoded file of trusted CA certificates.
+//!
+//! Since servers connect to each other by address rather than by name, every
+//! certificate is verified against a single configured domain name, which
+//! should appear in the subject alternative names of all server certificates.
+
+use std::io::BufReader;
+use std::path::Path;
+
+use tokio::prelude::*;
+use tokio_rustls::{rustls, webpki, TlsAcceptor, TlsConnector};
+use tokio_rustls::rustls::internal::pemfile;
+
+use crate::external;
+
+/// Certificates and keys for encrypting connections.
+#[derive(Clone)]
+pub struct Tls {
+    /// Server-side configuration, if a certificate was provided
+    acceptor: Option<TlsAcceptor>,
+
+    /// Client-side configuration
+    connector: TlsConnector,
+
+    /// Name that remote certificates are verified against
+    domain: String,
+}
+
+/// Failure to load TLS configuration.
+#[derive(Debug)]
+pub enum Error {
+    /// File could not be opened
+    Io(std::io::Error),
+
+    /// Certificate file contains no PEM-encoded certificates
+    Certificate,
+
+    /// Key file contains no PEM-encoded PKCS8 or RSA private key
+    Key,
+
+    /// CA file contains no valid PEM-encoded certificates
+    Authority,
+
+    /// Domain is not a valid DNS name
+    Domain(String),
+
+    /// Certificate and key were rejected by `rustls`
+    Rejected(rustls::TLSError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+        | Error::Io(error) => write!(fmt, "could not open file: {}", error),
+        | Error::Certificate => write!(fmt, "no certificates found in certificate file"),
+        | Error::Key => write!(fmt, "no private key found in key file"),
+        | Error::Authority => write!(fmt, "no valid certificates found in CA file"),
+        | Error::Domain(domain) => write!(fmt, "invalid domain name {}", domain),
+        | Error::Rejected(error) => write!(fmt, "invalid certificate or key: {:?}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+        | Error::Io(error) => Some(error),
+        | _ => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for Tls {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("Tls")
+            .field("server", &self.acceptor.is_some())
+            .field("domain", &self.domain)
+            .finish()
+    }
+}
+
+impl Tls {
+    /// Configure a server presenting certificate chain `cert` and private key `key`,
+    /// which verifies other servers against the CA certificates in `ca` and `domain`.
+    pub fn new<P: AsRef<Path>>(cert: P, key: P, ca: P, domain: &str) -> Result<Self, Error> {
+        let certs = pemfile::certs(&mut open(&cert)?)
+            .ok()
+            .filter(|certs| !certs.is_empty())
+            .ok_or(Error::Certificate)?;
+
+        let key = pemfile::pkcs8_private_keys(&mut open(&key)?)
+            .ok()
+            .filter(|keys| !keys.is_empty())
+            .or_else(|| pemfile::rsa_private_keys(&mut open(&key).ok()?).ok())
+            .and_then(|mut keys| keys.pop())
+            .ok_or(Error::Key)?;
+
+        let mut config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        config.set_single_cert(certs, key).map_err(Error::Rejected)?;
+
+        let mut tls = Tls::client(ca, domain)?;
+        tls.acceptor = Some(TlsAcceptor::from(std::sync::Arc::new(config)));
+        Ok(tls)
+    }
+
+    /// Configure a client that verifies servers against the CA certificates in `ca`
+    /// and `domain`, without presenting a certificate of its own.
+    pub fn client<P: AsRef<Path>>(ca: P, domain: &str) -> Result<Self, Error> {
+        webpki::DNSNameRef::try_from_ascii_str(domain)
+            .map_err(|_| Error::Domain(domain.to_string()))?;
+
+        let mut config = rustls::ClientConfig::new();
+        match config.root_store.add_pem_file(&mut open(&ca)?) {
+        | Ok((valid, _)) if valid > 0 => (),
+        | _ => return Err(Error::Authority),
+        }
+
+        Ok(Tls {
+            acceptor: None,
+            connector: TlsConnector::from(std::sync::Arc::new(config)),
+            domain: domain.to_string(),
+        })
+    }
+
+    /// Perform a client-side handshake over `stream`.
+    pub fn connect<S: external::Io>(&self, stream: S) -> impl Future<Item = impl external::Io, Error = std::io::Error> {
+        let domain = webpki::DNSNameRef::try_from_ascii_str(&self.domain)
+            .expect("[INTERNAL ERROR]: domain validated on creation");
+        self.connector.connect(domain, stream)
+    }
+
+    /// Perform a server-side handshake over `stream`.
+    pub fn accept<S: external::Io>(&self, stream: S) -> impl Future<Item = impl external::Io, Error = std::io::Error> {
+        match &self.acceptor {
+        | Some(acceptor) => future::Either::A(acceptor.accept(stream)),
+        | None => future::Either::B(future::err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "no server certificate configured",
+        ))),
+        }
+    }
+}
+
+fn open<P: AsRef<Path>>(path: P) -> Result<BufReader<std::fs::File>, Error> {
+    std::fs::File::open(path)
+        .map(BufReader::new)
+        .map_err(Error::Io)
+}
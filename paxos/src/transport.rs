@@ -0,0 +1,398 @@
+//! # Summary
+//!
+//! This module abstracts over how servers listen for and establish connections,
+//! so that the same protocol can run over any byte stream.
+//!
+//! Every endpoint is identified by a port number, as in `Config`, which each
+//! transport maps into its own address space:
+//!
+//! - `Tcp` listens on and connects to `127.0.0.1:<PORT>`
+//! - `Unix` listens on and connects to `<DIR>/paxos-<PORT>.sock`
+//! - `Memory` connects endpoints within a single process, for testing
+//! - `Tls` encrypts the connections of any other transport
+//!
+//! Incoming connections are yielded as pending futures rather than streams, so
+//! that a slow or failed handshake on one connection doesn't hold up the others.
+
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures::sync::mpsc;
+use parking_lot::Mutex;
+use tokio::prelude::*;
+
+use crate::external;
+use crate::tls;
+
+/// Connection that is still being established.
+pub type Pending = Box<dyn Future<Item = Box<dyn external::Io>, Error = io::Error> + Send>;
+
+/// Incoming connections on a single endpoint.
+pub type Incoming = Box<dyn Stream<Item = Pending, Error = io::Error> + Send>;
+
+/// Means of listening for and establishing connections.
+pub trait Transport: Send + Sync + 'static {
+    /// Listens for connections to endpoint `port`.
+    fn listen(&self, port: usize) -> io::Result<Incoming>;
+
+    /// Connects to endpoint `port`.
+    fn connect(&self, port: usize) -> Pending;
+}
+
+impl Transport for Arc<dyn Transport> {
+    fn listen(&self, port: usize) -> io::Result<Incoming> {
+        (**self).listen(port)
+    }
+
+    fn connect(&self, port: usize) -> Pending {
+        (**self).connect(port)
+    }
+}
+
+/// TCP connections on the loopback interface.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Tcp;
+
+impl Tcp {
+    fn address(port: usize) -> io::Result<std::net::SocketAddr> {
+        format!("127.0.0.1:{}", port)
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid port {}", port)))
+    }
+}
+
+impl Transport for Tcp {
+    fn listen(&self, port: usize) -> io::Result<Incoming> {
+        let incoming = tokio::net::TcpListener::bind(&Tcp::address(port)?)?
+            .incoming()
+            .map(|stream| Box::new(future::ok(Box::new(stream) as Box<dyn external::Io>)) as Pending);
+        Ok(Box::new(incoming))
+    }
+
+    fn connect(&self, port: usize) -> Pending {
+        match Tcp::address(port) {
+        | Ok(address) => Box::new(
+            tokio::net::TcpStream::connect(&address).map(|stream| Box::new(stream) as Box<dyn external::Io>)
+        ),
+        | Err(error) => Box::new(future::err(error)),
+        }
+    }
+}
+
+/// Unix domain socket connections, with one socket per endpoint in a single directory.
+#[derive(Clone, Debug)]
+pub struct Unix {
+    dir: PathBuf,
+}
+
+impl Unix {
+    /// Place sockets in directory `dir`.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Unix { dir: dir.into() }
+    }
+
+    /// Listens for connections on the socket at `path`, replacing any stale socket left
+    /// behind by a previous listener. Fails instead of replacing any other kind of file.
+    /// The socket is removed once the returned connections are dropped.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Incoming> {
+        let path = path.as_ref().to_path_buf();
+        match std::fs::symlink_metadata(&path) {
+        | Ok(ref metadata) if metadata.file_type().is_socket() => std::fs::remove_file(&path)?,
+        | Ok(_) => {
+            let message = format!("{} already exists and is not a socket", path.display());
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, message))
+        }
+        | Err(ref error) if error.kind() == io::ErrorKind::NotFound => (),
+        | Err(error) => return Err(error),
+        }
+        let incoming = tokio::net::UnixListener::bind(&path)?.incoming();
+        Ok(Box::new(Socket { incoming, path }))
+    }
+
+    fn path(&self, port: usize) -> PathBuf {
+        self.dir.join(format!("paxos-{}.sock", port))
+    }
+}
+
+impl Transport for Unix {
+    fn listen(&self, port: usize) -> io::Result<Incoming> {
+        Unix::bind(self.path(port))
+    }
+
+    fn connect(&self, port: usize) -> Pending {
+        Box::new(
+            tokio::net::UnixStream::connect(self.path(port)).map(|stream| Box::new(stream) as Box<dyn external::Io>)
+        )
+    }
+}
+
+/// Incoming connections on a Unix domain socket, which is removed when dropped.
+struct Socket {
+    incoming: tokio::net::unix::Incoming,
+    path: PathBuf,
+}
+
+impl Stream for Socket {
+    type Item = Pending;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.incoming.poll().map(|ready| ready.map(|stream| {
+            stream.map(|stream| Box::new(future::ok(Box::new(stream) as Box<dyn external::Io>)) as Pending)
+        }))
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// TLS-encrypted connections over an underlying transport.
+#[derive(Clone)]
+pub struct Tls<T> {
+    inner: T,
+    tls: tls::Tls,
+}
+
+impl<T: Transport> Tls<T> {
+    /// Encrypt connections over `inner` with `tls`.
+    pub fn new(inner: T, tls: tls::Tls) -> Self {
+        Tls { inner, tls }
+    }
+}
+
+impl<T: Transport> Transport for Tls<T> {
+    fn listen(&self, port: usize) -> io::Result<Incoming> {
+        let tls = self.tls.clone();
+        let incoming = self.inner.listen(port)?.map(move |connecting| {
+            let tls = tls.clone();
+            let accepting = connecting
+                .and_then(move |stream| tls.accept(stream))
+                .map(|stream| Box::new(stream) as Box<dyn external::Io>)
+                .map_err(|error| {
+                    warn!("TLS handshake failed: {}", error);
+                    error
+                });
+            Box::new(accepting) as Pending
+        });
+        Ok(Box::new(incoming))
+    }
+
+    fn connect(&self, port: usize) -> Pending {
+        let tls = self.tls.clone();
+        let connecting = self.inner.connect(port)
+            .and_then(move |stream| {
+                tls.connect(stream).map_err(|error| {
+                    warn!("TLS handshake failed: {}", error);
+                    error
+                })
+            })
+            .map(|stream| Box::new(stream) as Box<dyn external::Io>);
+        Box::new(connecting)
+    }
+}
+
+/// In-process connections between endpoints that share the same `Memory`,
+/// which can be cloned and passed to every server under test.
+#[derive(Clone, Default)]
+pub struct Memory {
+    listeners: Arc<Mutex<HashMap<usize, mpsc::UnboundedSender<Box<dyn external::Io>>>>>,
+}
+
+impl std::fmt::Debug for Memory {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut ports = self.listeners.lock().keys().cloned().collect::<Vec<_>>();
+        ports.sort();
+        fmt.debug_struct("Memory").field("ports", &ports).finish()
+    }
+}
+
+impl Transport for Memory {
+    fn listen(&self, port: usize) -> io::Result<Incoming> {
+        let mut listeners = self.listeners.lock();
+        if listeners.get(&port).map_or(false, |tx| !tx.is_closed()) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("port {} is in use", port)))
+        }
+        let (tx, rx) = mpsc::unbounded();
+        listeners.insert(port, tx);
+        let incoming = rx
+            .map(|stream| Box::new(future::ok(stream)) as Pending)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "listener failed"));
+        Ok(Box::new(incoming))
+    }
+
+    fn connect(&self, port: usize) -> Pending {
+        let (client, server) = Pipe::pair();
+        let accepted = self.listeners.lock()
+            .get(&port)
+            .map_or(false, |tx| tx.unbounded_send(Box::new(server)).is_ok());
+        if accepted {
+            Box::new(future::ok(Box::new(client) as Box<dyn external::Io>))
+        } else {
+            Box::new(future::err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("nothing listening on port {}", port))))
+        }
+    }
+}
+
+/// One end of an in-memory connection. Reads return end-of-file once the
+/// other end is shut down or dropped.
+pub struct Pipe {
+    /// Sending half, which is dropped when this end is shut down
+    tx: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    rx: mpsc::UnboundedReceiver<Vec<u8>>,
+
+    /// Unread remainder of the last chunk received
+    buffer: Vec<u8>,
+
+    /// Offset of the first unread byte in `buffer`
+    offset: usize,
+}
+
+impl Pipe {
+    /// Create both ends of a new connection.
+    pub fn pair() -> (Pipe, Pipe) {
+        let (a_tx, a_rx) = mpsc::unbounded();
+        let (b_tx, b_rx) = mpsc::unbounded();
+        let a = Pipe { tx: Some(a_tx), rx: b_rx, buffer: Vec::new(), offset: 0 };
+        let b = Pipe { tx: Some(b_tx), rx: a_rx, buffer: Vec::new(), offset: 0 };
+        (a, b)
+    }
+}
+
+impl io::Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.buffer.len() {
+            match self.rx.poll() {
+            | Ok(Async::Ready(Some(chunk))) => {
+                self.buffer = chunk;
+                self.offset = 0;
+            }
+            | Ok(Async::Ready(None)) | Err(()) => return Ok(0),
+            | Ok(Async::NotReady) => return Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+        let len = std::cmp::min(buf.len(), self.buffer.len() - self.offset);
+        buf[..len].copy_from_slice(&self.buffer[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+impl io::Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+        self.tx.as_ref()
+            .ok_or(())
+            .and_then(|tx| tx.unbounded_send(buf.to_vec()).map_err(|_| ()))
+            .map(|_| buf.len())
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for Pipe {}
+
+impl AsyncWrite for Pipe {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.tx = None;
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Throwaway certificates for `localhost`, generated with `openssl req -x509` for
+    /// `ca.pem` and `other-ca.pem`, and signed by `ca.pem` for `server.pem`.
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+    }
+
+    /// Send a greeting from a TLS server to a client that trusts the CA in `ca`,
+    /// returning what the client received.
+    fn greet(ca: &str) -> io::Result<Vec<u8>> {
+        let memory = Memory::default();
+        let server = tls::Tls::new(fixture("server.pem"), fixture("server.key"), fixture("ca.pem"), "localhost").unwrap();
+        let client = tls::Tls::client(fixture(ca), "localhost").unwrap();
+        let server = Tls::new(memory.clone(), server);
+        let client = Tls::new(memory, client);
+
+        let incoming = server.listen(0).unwrap();
+        let accept = incoming.into_future()
+            .map_err(|(error, _)| error)
+            .and_then(|(connecting, _)| connecting.expect("[TEST ERROR]: listener closed"))
+            .and_then(|stream| tokio::io::write_all(stream, b"hello"))
+            .map(|_| ())
+            .map_err(|_| ());
+        let connect = client.connect(0)
+            .and_then(|stream| tokio::io::read_exact(stream, vec![0; 5]))
+            .map(|(_, greeting)| greeting);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(accept);
+        runtime.block_on(connect)
+    }
+
+    #[test]
+    fn tls_connects_with_trusted_ca() {
+        assert_eq!(greet("ca.pem").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn tls_rejects_untrusted_ca() {
+        assert!(greet("other-ca.pem").is_err());
+    }
+
+    /// Fresh directory for the sockets of a single test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("paxos-transport-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn pipe_shutdown_closes_other_end() {
+        let (mut a, b) = Pipe::pair();
+        let read = future::lazy(move || {
+            a.write_all(b"data").unwrap();
+            a.shutdown().unwrap();
+            assert!(a.write(b"more").is_err());
+            tokio::io::read_to_end(b, Vec::new())
+        });
+        let (_, bytes) = tokio::runtime::Runtime::new().unwrap().block_on(read).unwrap();
+        assert_eq!(bytes, b"data");
+    }
+
+    #[test]
+    fn unix_refuses_to_replace_other_files() {
+        let dir = scratch("refuse");
+        let path = dir.join("paxos-0.sock");
+        std::fs::write(&path, b"data").unwrap();
+        assert!(Unix::bind(&path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"data");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unix_replaces_stale_sockets_and_removes_its_own() {
+        let dir = scratch("replace");
+        let path = dir.join("paxos-0.sock");
+        let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        std::mem::drop(stale);
+        let incoming = Unix::bind(&path).unwrap();
+        assert!(path.exists());
+        std::mem::drop(incoming);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
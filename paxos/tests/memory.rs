@@ -0,0 +1,127 @@
+#![feature(await_macro, async_await, futures_api)]
+
+#[macro_use]
+extern crate tokio;
+
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::prelude::*;
+
+use paxos::external::{Rx, Tx};
+use paxos::transport::{Memory, Transport};
+
+const COUNT: usize = 3;
+const PORT: usize = 10000;
+const STARTUP: Duration = Duration::from_millis(500);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Mode {
+    Get,
+    Put(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Command {
+    client_id: usize,
+    local_id: usize,
+    mode: Mode,
+}
+
+impl paxos::Command for Command {
+    type ClientID = usize;
+    type LocalID = usize;
+    fn client_id(&self) -> usize { self.client_id }
+    fn local_id(&self) -> usize { self.local_id }
+}
+
+#[derive(Default)]
+struct Log(Vec<String>);
+
+impl paxos::State for Log {
+    type Command = Command;
+    type Response = Vec<String>;
+    fn execute(&mut self, _: usize, command: Command) -> Option<Vec<String>> {
+        match command.mode {
+        | Mode::Get => Some(self.0.clone()),
+        | Mode::Put(message) => { self.0.push(message); None }
+        }
+    }
+    fn tag() -> &'static str { "log" }
+}
+
+/// Connect to the server listening for clients on `port` and negotiate the connection.
+async fn connect(memory: Memory, cluster: paxos::Cluster, port: usize) -> Option<(Rx<Vec<String>>, Tx<Command>)> {
+    let stream = await!(memory.connect(port)).ok()?;
+    let stream = await!(paxos::handshake::connect::<Log, _>(stream, cluster, paxos::Codec::Bincode, Vec::new())).ok()?;
+    Some(paxos::external::new(stream))
+}
+
+/// Put a message through each of the first two servers, then read the third
+/// server's log until it contains both, or until it has been polled too often.
+async fn exchange(memory: Memory, cluster: paxos::Cluster) -> Option<Vec<String>> {
+    await!(tokio::timer::Delay::new(Instant::now() + STARTUP)).ok()?;
+
+    let mut connections = Vec::new();
+    for (id, message) in ["a", "b"].iter().enumerate() {
+        let (rx, mut tx) = await!(connect(memory.clone(), cluster, PORT + id))?;
+        let put = Command { client_id: id, local_id: 0, mode: Mode::Put(message.to_string()) };
+        let writer = &mut tx;
+        await!(writer.send(put)).ok()?;
+        connections.push((rx, tx));
+    }
+
+    let (mut rx, mut tx) = await!(connect(memory.clone(), cluster, PORT + 2))?;
+    for local_id in 0..50 {
+        let get = Command { client_id: 2, local_id, mode: Mode::Get };
+        let writer = &mut tx;
+        await!(writer.send(get)).ok()?;
+        let (log, next) = await!(rx.into_future()).ok()?;
+        rx = next;
+        if log.as_ref().map_or(false, |log| log.len() == 2) {
+            return log
+        }
+        await!(tokio::timer::Delay::new(Instant::now() + STARTUP / 5)).ok()?;
+    }
+    None
+}
+
+#[test]
+fn cluster_runs_over_memory_transport() {
+    let memory = Memory::default();
+    let cluster = paxos::Cluster::new_v4();
+    let storage = std::env::temp_dir().join(format!("paxos-memory-{}", cluster));
+    std::fs::create_dir_all(&storage).unwrap();
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let configs = (0..COUNT)
+        .map(|id| {
+            paxos::Config::<Log>::new(id, PORT + id, COUNT)
+                .with_transport(memory.clone())
+                .with_cluster(cluster)
+                .with_storage(&storage)
+        })
+        .collect::<Vec<_>>();
+    runtime.spawn(future::lazy(move || {
+        for config in configs {
+            tokio::spawn_async(config.run());
+        }
+        tokio::spawn_async(async move {
+            let log = await!(exchange(memory, cluster));
+            tx.send(log).ok();
+        });
+        Ok(())
+    }));
+
+    let log = rx.recv_timeout(TIMEOUT).ok().and_then(|log| log);
+    runtime.shutdown_now().wait().ok();
+    std::fs::remove_dir_all(&storage).ok();
+
+    let mut log = log.expect("[TEST ERROR]: cluster did not replicate both messages");
+    log.sort();
+    assert_eq!(log, vec!["a".to_string(), "b".to_string()]);
+}
@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde_derive::{Deserialize, Serialize};
+
+use paxos::simulate::Simulation;
+
+const COUNT: usize = 5;
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+enum Mode {
+    Get,
+    Put(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+struct Command {
+    client_id: usize,
+    local_id: usize,
+    mode: Mode,
+}
+
+impl paxos::Command for Command {
+    type ClientID = usize;
+    type LocalID = usize;
+    fn client_id(&self) -> usize { self.client_id }
+    fn local_id(&self) -> usize { self.local_id }
+}
+
+#[derive(Default)]
+struct Log(Vec<String>);
+
+impl paxos::State for Log {
+    type Command = Command;
+    type Response = (usize, Vec<String>);
+    fn execute(&mut self, _: usize, command: Command) -> Option<Self::Response> {
+        match command.mode {
+        | Mode::Get => Some((command.local_id, self.0.clone())),
+        | Mode::Put(message) => { self.0.push(message); None }
+        }
+    }
+    fn tag() -> &'static str { "log" }
+}
+
+/// Drives a simulation with a random workload derived from its seed.
+struct Driver {
+    simulation: Simulation<Log>,
+    random: StdRng,
+    running: Vec<bool>,
+    local_id: usize,
+
+    /// Every log observed so far, along with the observing server
+    logs: Vec<(usize, Vec<String>)>,
+}
+
+impl Driver {
+    fn new(seed: u64, drop_rate: f64) -> Self {
+        let mut simulation = Simulation::new(seed, COUNT)
+            .with_timeout(TIMEOUT)
+            .with_drop_rate(drop_rate);
+        for id in 0..COUNT {
+            simulation.start(id);
+        }
+        Driver {
+            simulation,
+            random: StdRng::seed_from_u64(seed),
+            running: vec![true; COUNT],
+            local_id: 0,
+            logs: Vec::new(),
+        }
+    }
+
+    fn request(&mut self, id: usize, mode: Mode) -> usize {
+        let local_id = self.local_id;
+        self.local_id += 1;
+        self.simulation.request(id, Command { client_id: id, local_id, mode });
+        local_id
+    }
+
+    /// Record all logs observed since the last call.
+    fn collect(&mut self) {
+        let responses = self.simulation.responses();
+        self.logs.extend(responses.into_iter().map(|(id, (_, log))| (id, log)));
+    }
+
+    /// Issue `operations` random puts and gets, crashing and restarting a
+    /// minority of servers along the way if `crashes` is set.
+    fn run(&mut self, operations: usize, crashes: bool) {
+        for operation in 0..operations {
+            let id = self.random.gen_range(0, COUNT);
+            let up = self.running.iter().filter(|up| **up).count();
+            match self.random.gen_range(0, 10) {
+            | 0 if crashes && self.running[id] && up > COUNT / 2 + 1 => {
+                self.simulation.crash(id);
+                self.running[id] = false;
+            }
+            | 0 | 1 if crashes && !self.running[id] => {
+                self.simulation.start(id);
+                self.running[id] = true;
+            }
+            | 2 | 3 => { self.request(id, Mode::Get); }
+            | _ => { self.request(id, Mode::Put(operation.to_string())); }
+            }
+            self.simulation.run_for(TIMEOUT / 10);
+        }
+        self.collect();
+    }
+
+    /// Restart every server and let the cluster quiesce.
+    fn settle(&mut self) {
+        for id in 0..COUNT {
+            self.simulation.start(id);
+            self.running[id] = true;
+        }
+        self.simulation.run_for(TIMEOUT * 20);
+    }
+
+    /// Ask every server for its log, returning the logs that were observed in time.
+    fn observe(&mut self) -> Vec<(usize, Vec<String>)> {
+        let requests = (0..COUNT)
+            .map(|id| self.request(id, Mode::Get))
+            .collect::<Vec<_>>();
+        self.simulation.run_for(TIMEOUT * 10);
+        let responses = self.simulation.responses();
+        self.logs.extend(responses.iter().map(|(id, (_, log))| (*id, log.clone())));
+        responses.into_iter()
+            .filter(|(_, (local_id, _))| requests.contains(local_id))
+            .map(|(id, (_, log))| (id, log))
+            .collect()
+    }
+
+    /// Ask every server for its log until they all observe the same one,
+    /// since a read can be ordered before writes that are still in flight.
+    fn converge(&mut self, seed: u64) {
+        for _ in 0..10 {
+            let logs = self.observe();
+            if logs.len() == COUNT && logs.iter().all(|(_, log)| *log == logs[0].1) {
+                return
+            }
+        }
+        panic!("seed {}: replicas did not converge", seed)
+    }
+}
+
+/// Asserts that every observed log is a prefix of one common sequence,
+/// and that no message appears twice.
+fn assert_consistent(seed: u64, logs: &[(usize, Vec<String>)]) {
+    for (l_id, left) in logs {
+        let mut unique = left.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), left.len(), "seed {}: server {} duplicated a message: {:?}", seed, l_id, left);
+        for (r_id, right) in logs {
+            let agree = left.iter().zip(right).all(|(l, r)| l == r);
+            assert!(agree, "seed {}: servers {} and {} diverged:\n  {:?}\n  {:?}", seed, l_id, r_id, left, right);
+        }
+    }
+}
+
+#[test]
+fn replicas_agree_under_reordering() {
+    for seed in 0..10 {
+        let mut driver = Driver::new(seed, 0.0);
+        driver.run(50, false);
+        driver.settle();
+        driver.converge(seed);
+        assert_consistent(seed, &driver.logs);
+    }
+}
+
+#[test]
+fn replicas_agree_under_drops() {
+    for seed in 0..10 {
+        let mut driver = Driver::new(seed, 0.2);
+        driver.run(50, false);
+        driver.settle();
+        driver.converge(seed);
+        assert_consistent(seed, &driver.logs);
+    }
+}
+
+#[test]
+fn replicas_agree_across_crashes() {
+    for seed in 0..10 {
+        let mut driver = Driver::new(seed, 0.0);
+        driver.run(50, true);
+        driver.settle();
+        driver.converge(seed);
+        assert_consistent(seed, &driver.logs);
+    }
+}
+
+#[test]
+fn simulations_are_deterministic() {
+    let run = |seed| {
+        let mut driver = Driver::new(seed, 0.1);
+        driver.run(30, true);
+        driver.settle();
+        driver.observe();
+        driver.logs
+    };
+    assert_eq!(run(7), run(7));
+}